@@ -0,0 +1,185 @@
+//! Measures protocol parsing throughput on recorded server traffic.
+//!
+//! `baseline` replays the copying parser that `Message::parse` replaced and
+//! `message` the receive loop's path through `Message::parse`, both of which
+//! update the bot. `borrowed` and `owned` only parse the lines.
+//!
+//! Run with `cargo bench --bench parse`. Set `PARSE_ITERATIONS` to change
+//! how many times the traffic is replayed.
+
+extern crate showdown;
+
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use showdown::{Bot, Message, Rank, Room, ServerMessage, Target, User};
+
+const TRAFFIC: &'static str = include_str!("traffic.txt");
+const CONFIG_PATH: &'static str = "examples/example_config.toml";
+
+/// Splits the recording into frames the way the server sends them. A line
+/// starting with `>` starts a frame for that room.
+fn frames(traffic: &str) -> Vec<String> {
+    let mut frames: Vec<String> = Vec::new();
+    for line in traffic.lines() {
+        if line.starts_with('>') || frames.is_empty() {
+            frames.push(String::new());
+        }
+        let frame = frames.last_mut().unwrap();
+        if !frame.is_empty() {
+            frame.push('\n');
+        }
+        frame.push_str(line);
+    }
+    frames
+}
+
+/// Parses every line of every frame, skipping room headers like the
+/// receive loop does, and returns how many lines were parsed.
+fn parse_frames(frames: &[String], owned: bool) -> usize {
+    let mut count = 0;
+    for frame in frames {
+        let frame = frame.trim();
+        let mut lines = frame.split('\n');
+        if frame.starts_with('>') {
+            lines.next();
+        }
+        for line in lines {
+            let message = ServerMessage::parse(line).unwrap();
+            if owned {
+                let _ = message.into_owned();
+            }
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Parses every line the way the receive loop and `Message::from_string`
+/// did before lines were borrowed: each line is copied into a new string
+/// behind its room header and split again, its command, parameters, user
+/// and payload are copied out, and the bot is locked for each update.
+fn parse_frames_baseline(frames: &[String], bot: &Arc<Mutex<Bot>>) -> usize {
+    let mut count = 0;
+    for frame in frames {
+        let mut room = "";
+        let mut messages: Vec<&str> = frame.trim().split("\n").collect();
+        if messages[0].starts_with(">") {
+            room = messages[0];
+            messages = messages[1..].to_vec();
+        }
+
+        for message in messages {
+            let text = format!("{}\n{}", room, message);
+            let (header, line) = match text.find('\n') {
+                Some(i) => (&text[..i], &text[i + 1..]),
+                None => ("", &text[..]),
+            };
+            let room = if header.starts_with('>') { &header[1..] } else { "" };
+            let kind = ServerMessage::parse(line).unwrap().into_owned();
+
+            let mut command = String::new();
+            let mut params: Vec<String> = Vec::new();
+            if line.starts_with('|') {
+                let mut vb_delim = line[1..].split('|');
+                command = vb_delim.next().unwrap_or("").to_lowercase();
+                params = vb_delim.map(String::from).collect();
+            }
+
+            let mut user = String::new();
+            let sender = match &*command {
+                "c:" => params.get(1),
+                "pm" | "c" | "j" | "l" | "n" => params.get(0),
+                _ => None,
+            };
+            if let Some(sender) = sender {
+                let (_, u, _) = Rank::split_user(sender);
+                user = u.to_string();
+            }
+            let payload = line.to_string();
+
+            if !room.is_empty() {
+                bot.lock().unwrap().room_map.insert(room);
+            }
+            if !user.is_empty() {
+                bot.lock().unwrap().user_map.insert(&user);
+            }
+            if !(user.is_empty() || room.is_empty()) {
+                bot.lock().unwrap()
+                    .room_map.insert_user_in_room(&user, room);
+                bot.lock().unwrap()
+                    .user_map.set_rank_of_user_in_room(Rank::Regular, &user,
+                                                       room);
+            }
+
+            let room: Room = Target::new(room);
+            let user: User = Target::new(&user);
+            let _ = (room, user, kind, params, payload);
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Parses every line into a `Message` like the receive loop does, which
+/// also updates the bot's rooms and users.
+fn parse_messages(frames: &[String], bot: &Arc<Mutex<Bot>>) -> usize {
+    let mut count = 0;
+    for frame in frames {
+        let frame = frame.trim();
+        let mut lines = frame.split('\n');
+        let header = if frame.starts_with('>') {
+            lines.next().unwrap_or("")
+        } else {
+            ""
+        };
+        let room = header.trim_left_matches('>');
+        for line in lines {
+            let _ = Message::parse(room, line, bot).unwrap();
+            count += 1;
+        }
+    }
+    count
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+fn run<F>(name: &str, frames: &[String], iterations: usize, parse: F)
+    where F: Fn(&[String]) -> usize
+{
+    let bytes: usize = frames.iter().map(|f| f.len()).sum();
+
+    // Warm up before timing
+    parse(frames);
+
+    let start = Instant::now();
+    let mut lines = 0;
+    for _ in 0..iterations {
+        lines += parse(frames);
+    }
+    let elapsed = seconds(start.elapsed());
+
+    println!("{:<10} {:>12.0} lines/s {:>10.1} MB/s",
+             name,
+             lines as f64 / elapsed,
+             (bytes * iterations) as f64 / elapsed / 1_000_000.0);
+}
+
+fn main() {
+    let iterations = env::var("PARSE_ITERATIONS").ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(20_000);
+    let frames = frames(TRAFFIC);
+
+    let bot = Arc::new(Mutex::new(Bot::new(CONFIG_PATH).unwrap()));
+
+    println!("{} frames, {} iterations", frames.len(), iterations);
+    run("baseline", &frames, iterations,
+        |f| parse_frames_baseline(f, &bot));
+    run("message", &frames, iterations, |f| parse_messages(f, &bot));
+    run("borrowed", &frames, iterations, |f| parse_frames(f, false));
+    run("owned", &frames, iterations, |f| parse_frames(f, true));
+}
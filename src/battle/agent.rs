@@ -0,0 +1,181 @@
+use std::fmt::Debug;
+
+use rand::{self, Rng};
+
+use super::choice::{Action, Choice};
+use super::request::{PokemonRequest, Request};
+use super::state::BattleState;
+
+/// A `BattleAgent` plays the bot's battles. It is asked for a `Choice`
+/// whenever a request needs an answer, once the events leading up to the
+/// request are in the `BattleState`. The choice is sent with the request id
+/// of the request it answers.
+pub trait BattleAgent: Send + Debug {
+    fn choose(&mut self, request: &Request, state: &BattleState) -> Choice;
+}
+
+/// A `BattleAgent` that picks a random legal choice.
+#[derive(Debug, Default)]
+pub struct RandomAgent;
+
+impl RandomAgent {
+    pub fn new() -> Box<BattleAgent> {
+        Box::new(RandomAgent)
+    }
+}
+
+/// Returns the team slots, counting from 1, of Pokemon that can be switched
+/// in and are not in `taken`.
+fn switches(team: &[PokemonRequest], taken: &[usize]) -> Vec<usize> {
+    team.iter()
+        .enumerate()
+        .filter(|&(_, p)| !p.active && !p.is_fainted())
+        .map(|(i, _)| i + 1)
+        .filter(|i| !taken.contains(i))
+        .collect()
+}
+
+/// Picks a target for a move of the Pokemon in `position`, counting from 0,
+/// if the move needs one.
+fn target<R: Rng>(rng: &mut R, target: &str, position: usize,
+                  active: usize) -> Option<i32> {
+    if active < 2 {
+        return None;
+    }
+
+    let active = active as i32;
+    let own = position as i32 + 1;
+    match target {
+        "normal" | "any" | "adjacentFoe" => Some(rng.gen_range(1, active + 1)),
+        "adjacentAllyOrSelf" => Some(-own),
+        "adjacentAlly" => Some(-(if own < active { own + 1 } else { own - 1 })),
+        _ => None,
+    }
+}
+
+impl BattleAgent for RandomAgent {
+    fn choose(&mut self, request: &Request, _: &BattleState) -> Choice {
+        let mut rng = rand::thread_rng();
+        let team = &request.side.pokemon;
+
+        if request.team_preview {
+            let mut order: Vec<usize> = (1..team.len() + 1).collect();
+            rng.shuffle(&mut order);
+            return Choice::Team(order);
+        }
+
+        let mut taken = Vec::new();
+        let mut actions = Vec::new();
+
+        if !request.force_switch.is_empty() {
+            for &forced in &request.force_switch {
+                let action = match rng.choose(&switches(team, &taken)) {
+                    Some(&slot) if forced => {
+                        taken.push(slot);
+                        Action::Switch(slot)
+                    },
+                    _ => Action::Pass,
+                };
+                actions.push(action);
+            }
+            return Choice::Actions(actions);
+        }
+
+        if request.active.is_empty() {
+            return Choice::Default;
+        }
+
+        for (i, active) in request.active.iter().enumerate() {
+            if team.get(i).map_or(true, |p| p.is_fainted()) {
+                actions.push(Action::Pass);
+                continue;
+            }
+
+            let mut options: Vec<Action> = active.moves.iter()
+                .enumerate()
+                .filter(|&(_, m)| !m.disabled)
+                .map(|(slot, m)| {
+                    let action = Action::use_move(slot + 1);
+                    match target(&mut rng, &m.target, i, request.active.len()) {
+                        Some(target) => action.at(target),
+                        None => action,
+                    }
+                })
+                .collect();
+            if !active.trapped {
+                options.extend(switches(team, &taken).into_iter()
+                               .map(Action::Switch));
+            }
+
+            match rng.choose(&options).cloned() {
+                Some(action) => {
+                    if let Action::Switch(slot) = action {
+                        taken.push(slot);
+                    }
+                    actions.push(action);
+                },
+                None => return Choice::Default,
+            }
+        }
+
+        Choice::Actions(actions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BattleAgent, RandomAgent};
+    use battle::choice::{Action, Choice};
+    use battle::request::Request;
+    use battle::state::BattleState;
+
+    #[test]
+    fn random_agent_test() {
+        let state = BattleState::new("battle-gen9ou-1");
+        let mut agent = RandomAgent;
+
+        // Trapped with one usable move leaves one legal choice
+        let request = Request::parse(r#"{
+            "active": [{"trapped": true, "moves": [
+                {"move": "Protect", "id": "protect", "disabled": true},
+                {"move": "Tackle", "id": "tackle", "pp": 35}
+            ]}],
+            "side": {"pokemon": [
+                {"ident": "p1: A", "details": "A", "condition": "10/100",
+                 "active": true},
+                {"ident": "p1: B", "details": "B", "condition": "100/100"}
+            ]},
+            "rqid": 1
+        }"#).unwrap();
+        assert_eq!(agent.choose(&request, &state),
+                   Choice::Actions(vec![Action::use_move(2)]));
+
+        // Only the Pokemon that has not fainted can come in
+        let request = Request::parse(r#"{
+            "forceSwitch": [true],
+            "side": {"pokemon": [
+                {"ident": "p1: A", "details": "A", "condition": "0 fnt",
+                 "active": true},
+                {"ident": "p1: B", "details": "B", "condition": "0 fnt"},
+                {"ident": "p1: C", "details": "C", "condition": "50/100"}
+            ]}
+        }"#).unwrap();
+        assert_eq!(agent.choose(&request, &state),
+                   Choice::Actions(vec![Action::Switch(3)]));
+
+        let request = Request::parse(r#"{
+            "teamPreview": true,
+            "side": {"pokemon": [
+                {"ident": "p1: A", "details": "A", "condition": "100/100"},
+                {"ident": "p1: B", "details": "B", "condition": "100/100"}
+            ]}
+        }"#).unwrap();
+        match agent.choose(&request, &state) {
+            Choice::Team(mut order) => {
+                order.sort();
+                assert_eq!(order, vec![1, 2]);
+            },
+            choice => panic!("expected a team order, got {:?}", choice),
+        }
+    }
+}
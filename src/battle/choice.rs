@@ -0,0 +1,463 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use command::Command;
+use super::request::Request;
+
+/// A once per battle mechanic used along with a move.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Gimmick {
+    Terastallize,
+    Mega,
+    Dynamax,
+    ZMove,
+}
+
+impl fmt::Display for Gimmick {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Gimmick::Terastallize => write!(f, "terastallize"),
+            Gimmick::Mega => write!(f, "mega"),
+            Gimmick::Dynamax => write!(f, "dynamax"),
+            Gimmick::ZMove => write!(f, "zmove"),
+        }
+    }
+}
+
+/// What one active Pokemon does.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Use the move in `slot`, counting from 1. The target is only needed
+    /// in doubles and triples: foes are counted from 1 and allies from -1.
+    Move { slot: usize, target: Option<i32>, gimmick: Option<Gimmick> },
+    /// Switch to the Pokemon in `slot` of the team, counting from 1.
+    Switch(usize),
+    /// Do nothing, for positions that have nothing to do.
+    Pass,
+}
+
+impl Action {
+    /// Uses the move in `slot`, counting from 1.
+    pub fn use_move(slot: usize) -> Action {
+        Action::Move { slot, target: None, gimmick: None }
+    }
+
+    /// Aims a move at `target`. Does nothing to other actions.
+    pub fn at(self, target: i32) -> Action {
+        match self {
+            Action::Move { slot, gimmick, .. } =>
+                Action::Move { slot, target: Some(target), gimmick },
+            action => action,
+        }
+    }
+
+    /// Uses a `Gimmick` along with a move. Does nothing to other actions.
+    pub fn with(self, gimmick: Gimmick) -> Action {
+        match self {
+            Action::Move { slot, target, .. } =>
+                Action::Move { slot, target, gimmick: Some(gimmick) },
+            action => action,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::Move { slot, target, gimmick } => {
+                write!(f, "move {}", slot)?;
+                if let Some(target) = target {
+                    write!(f, " {}", target)?;
+                }
+                if let Some(gimmick) = gimmick {
+                    write!(f, " {}", gimmick)?;
+                }
+                Ok(())
+            },
+            Action::Switch(slot) => write!(f, "switch {}", slot),
+            Action::Pass => write!(f, "pass"),
+        }
+    }
+}
+
+/// A decision for a request.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Choice {
+    /// One action for each active Pokemon, in position order.
+    Actions(Vec<Action>),
+    /// The order to bring the team in, from team preview.
+    Team(Vec<usize>),
+    /// Let the server decide.
+    Default,
+}
+
+/// Why a `Choice` cannot answer a request. Positions count from 0 and
+/// slots from 1.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChoiceError {
+    /// The request has nothing to decide.
+    NothingToChoose,
+    /// The request is for a team order, or a team order was given for
+    /// another request.
+    WrongKind,
+    /// The number of actions does not match the number of positions.
+    ActionCount { expected: usize, actual: usize },
+    /// There is no move in the slot, or it is disabled.
+    InvalidMove { position: usize, slot: usize },
+    /// The move needs a target.
+    MissingTarget { position: usize },
+    /// The move cannot be aimed at the target.
+    InvalidTarget { position: usize, target: i32 },
+    /// The gimmick cannot be used, or is used more than once.
+    InvalidGimmick { position: usize, gimmick: Gimmick },
+    /// The Pokemon in the slot is missing, fainted, active, or already
+    /// switching in.
+    InvalidSwitch { position: usize, slot: usize },
+    /// The Pokemon cannot switch out.
+    Trapped { position: usize },
+    /// The Pokemon has to switch out, or has to act.
+    InvalidAction { position: usize },
+    /// The team order is not made of distinct team slots.
+    InvalidTeam,
+}
+
+impl fmt::Display for ChoiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChoiceError::NothingToChoose => write!(f, "nothing to choose"),
+            ChoiceError::WrongKind =>
+                write!(f, "choice does not match the kind of request"),
+            ChoiceError::ActionCount { expected, actual } =>
+                write!(f, "expected {} actions, got {}", expected, actual),
+            ChoiceError::InvalidMove { position, slot } =>
+                write!(f, "position {} cannot use move {}", position, slot),
+            ChoiceError::MissingTarget { position } =>
+                write!(f, "move of position {} needs a target", position),
+            ChoiceError::InvalidTarget { position, target } =>
+                write!(f, "position {} cannot target {}", position, target),
+            ChoiceError::InvalidGimmick { position, gimmick } =>
+                write!(f, "position {} cannot {}", position, gimmick),
+            ChoiceError::InvalidSwitch { position, slot } =>
+                write!(f, "position {} cannot switch to {}", position, slot),
+            ChoiceError::Trapped { position } =>
+                write!(f, "position {} is trapped", position),
+            ChoiceError::InvalidAction { position } =>
+                write!(f, "invalid action for position {}", position),
+            ChoiceError::InvalidTeam => write!(f, "invalid team order"),
+        }
+    }
+}
+
+impl StdError for ChoiceError {
+    fn description(&self) -> &str {
+        "invalid battle choice"
+    }
+}
+
+/// Returns true if a move with the target type needs a target when more
+/// than one Pokemon is active per side.
+fn needs_target(target: &str) -> bool {
+    match target {
+        "normal" | "any" | "adjacentFoe" | "adjacentAlly" |
+        "adjacentAllyOrSelf" => true,
+        _ => false,
+    }
+}
+
+/// Checks that a move target is on the field and fits the target type.
+fn valid_target(target_type: &str, target: i32, position: usize,
+                active: usize) -> bool {
+    let own = -(position as i32 + 1);
+    if target == 0 || target.abs() as usize > active {
+        return false;
+    }
+    match target_type {
+        "adjacentFoe" => target > 0,
+        "adjacentAlly" => target < 0 && target != own,
+        "adjacentAllyOrSelf" => target < 0,
+        _ => target != own,
+    }
+}
+
+impl Choice {
+    /// Starts building a choice for `request`.
+    pub fn builder<'a>(request: &'a Request) -> ChoiceBuilder<'a> {
+        ChoiceBuilder { request, actions: Vec::new() }
+    }
+
+    /// Returns the command answering the request with id `rqid`.
+    pub fn to_command(&self, rqid: Option<u64>) -> Command {
+        match *self {
+            Choice::Team(ref order) => Command::Team(
+                order.iter().map(|i| i.to_string()).collect(), rqid),
+            _ => Command::Choose(self.to_string(), rqid),
+        }
+    }
+
+    /// Checks that the choice is a legal answer to `request`.
+    pub fn validate(&self, request: &Request) -> Result<(), ChoiceError> {
+        if request.wait {
+            return Err(ChoiceError::NothingToChoose);
+        }
+
+        let team = &request.side.pokemon;
+        let actions = match (self, request.team_preview) {
+            (&Choice::Default, _) => return Ok(()),
+            (&Choice::Team(ref order), true) => {
+                let mut seen = Vec::new();
+                for &slot in order {
+                    if slot == 0 || slot > team.len() || seen.contains(&slot) {
+                        return Err(ChoiceError::InvalidTeam);
+                    }
+                    seen.push(slot);
+                }
+                return if seen.is_empty() {
+                    Err(ChoiceError::InvalidTeam)
+                } else {
+                    Ok(())
+                };
+            },
+            (&Choice::Actions(ref actions), false) => actions,
+            _ => return Err(ChoiceError::WrongKind),
+        };
+
+        let forced = !request.force_switch.is_empty();
+        let expected = if forced {
+            request.force_switch.len()
+        } else {
+            request.active.len()
+        };
+        if actions.len() != expected {
+            return Err(ChoiceError::ActionCount {
+                expected,
+                actual: actions.len(),
+            });
+        }
+
+        let mut switching = Vec::new();
+        let mut gimmick_used = false;
+        for (position, action) in actions.iter().enumerate() {
+            match *action {
+                Action::Pass => {
+                    // Only positions that have nothing to do can pass
+                    let idle = if forced {
+                        !request.force_switch[position]
+                    } else {
+                        team.get(position).map_or(true, |p| p.is_fainted())
+                    };
+                    if !idle {
+                        return Err(ChoiceError::InvalidAction { position });
+                    }
+                },
+                Action::Switch(slot) => {
+                    if forced && !request.force_switch[position] {
+                        return Err(ChoiceError::InvalidAction { position });
+                    }
+                    if !forced && request.active[position].trapped {
+                        return Err(ChoiceError::Trapped { position });
+                    }
+                    let available = slot > 0 && team.get(slot - 1)
+                        .map_or(false, |p| !p.active && !p.is_fainted());
+                    if !available || switching.contains(&slot) {
+                        return Err(ChoiceError::InvalidSwitch { position, slot });
+                    }
+                    switching.push(slot);
+                },
+                Action::Move { slot, target, gimmick } => {
+                    if forced {
+                        return Err(ChoiceError::InvalidAction { position });
+                    }
+                    let active = &request.active[position];
+                    let m = match active.moves.get(slot.wrapping_sub(1)) {
+                        Some(m) if !m.disabled => m,
+                        _ => return Err(ChoiceError::InvalidMove { position, slot }),
+                    };
+
+                    let multi = request.active.len() > 1;
+                    match target {
+                        Some(target) if !multi || !valid_target(
+                            &m.target, target, position, request.active.len()) =>
+                            return Err(ChoiceError::InvalidTarget {
+                                position,
+                                target,
+                            }),
+                        None if multi && needs_target(&m.target) =>
+                            return Err(ChoiceError::MissingTarget { position }),
+                        _ => (),
+                    }
+
+                    if let Some(gimmick) = gimmick {
+                        let allowed = match gimmick {
+                            Gimmick::Terastallize => active.can_terastallize,
+                            Gimmick::Mega => active.can_mega_evo,
+                            Gimmick::Dynamax => active.can_dynamax,
+                            Gimmick::ZMove => active.can_z_move,
+                        };
+                        if !allowed || gimmick_used {
+                            return Err(ChoiceError::InvalidGimmick {
+                                position,
+                                gimmick,
+                            });
+                        }
+                        gimmick_used = true;
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Choice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Choice::Actions(ref actions) => {
+                let actions: Vec<String> = actions.iter()
+                    .map(|a| a.to_string())
+                    .collect();
+                write!(f, "{}", actions.join(", "))
+            },
+            Choice::Team(ref order) => {
+                let order: Vec<String> = order.iter()
+                    .map(|i| i.to_string())
+                    .collect();
+                write!(f, "team {}", order.concat())
+            },
+            Choice::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// Builds a `Choice` one position at a time and checks it against the
+/// request it answers.
+#[derive(Debug)]
+pub struct ChoiceBuilder<'a> {
+    request: &'a Request,
+    actions: Vec<Action>,
+}
+
+impl<'a> ChoiceBuilder<'a> {
+    /// Adds the action of the next position.
+    pub fn action(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    pub fn use_move(self, slot: usize) -> Self {
+        self.action(Action::use_move(slot))
+    }
+
+    pub fn switch(self, slot: usize) -> Self {
+        self.action(Action::Switch(slot))
+    }
+
+    pub fn pass(self) -> Self {
+        self.action(Action::Pass)
+    }
+
+    /// Returns the choice, or why it cannot answer the request.
+    pub fn build(self) -> Result<Choice, ChoiceError> {
+        let choice = Choice::Actions(self.actions);
+        choice.validate(self.request)?;
+        Ok(choice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Choice, ChoiceError, Gimmick};
+    use battle::request::Request;
+
+    fn doubles() -> Request {
+        Request::parse(r#"{
+            "active": [
+                {"canTerastallize": "Fire", "moves": [
+                    {"move": "Flamethrower", "id": "flamethrower",
+                     "target": "normal"},
+                    {"move": "Protect", "id": "protect", "target": "self",
+                     "disabled": true}
+                ]},
+                {"trapped": true, "moves": [
+                    {"move": "Earthquake", "id": "earthquake",
+                     "target": "allAdjacent"}
+                ]}
+            ],
+            "side": {"pokemon": [
+                {"ident": "p1: A", "details": "A", "condition": "100/100",
+                 "active": true},
+                {"ident": "p1: B", "details": "B", "condition": "100/100",
+                 "active": true},
+                {"ident": "p1: C", "details": "C", "condition": "0 fnt"},
+                {"ident": "p1: D", "details": "D", "condition": "50/100"}
+            ]},
+            "rqid": 4
+        }"#).unwrap()
+    }
+
+    #[test]
+    fn to_command_test() {
+        let choice = Choice::Actions(vec![
+            Action::use_move(2).at(1).with(Gimmick::Terastallize),
+            Action::Switch(4),
+        ]);
+        assert_eq!(choice.to_command(Some(3)).to_line("battle-gen9doublesou-1"),
+                   "battle-gen9doublesou-1|/choose move 2 1 terastallize, switch 4|3");
+        assert_eq!(Choice::Team(vec![3, 1, 2, 4, 5, 6]).to_command(None)
+                   .to_line("battle-gen9ou-1"),
+                   "battle-gen9ou-1|/team 312456");
+        assert_eq!(Choice::Default.to_command(Some(7)).to_string(),
+                   "/choose default|7");
+    }
+
+    #[test]
+    fn builder_test() {
+        let request = doubles();
+        let choice = Choice::builder(&request)
+            .action(Action::use_move(1).at(2).with(Gimmick::Terastallize))
+            .use_move(1)
+            .build()
+            .unwrap();
+        assert_eq!(choice.to_string(), "move 1 2 terastallize, move 1");
+
+        assert_eq!(Choice::builder(&request).use_move(1).use_move(1).build(),
+                   Err(ChoiceError::MissingTarget { position: 0 }));
+        assert_eq!(Choice::builder(&request).use_move(2).use_move(1).build(),
+                   Err(ChoiceError::InvalidMove { position: 0, slot: 2 }));
+        assert_eq!(Choice::builder(&request).switch(3).use_move(1).build(),
+                   Err(ChoiceError::InvalidSwitch { position: 0, slot: 3 }));
+        assert_eq!(Choice::builder(&request).switch(2).use_move(1).build(),
+                   Err(ChoiceError::InvalidSwitch { position: 0, slot: 2 }));
+        assert_eq!(Choice::builder(&request).switch(4).switch(4).build(),
+                   Err(ChoiceError::Trapped { position: 1 }));
+        assert_eq!(Choice::builder(&request).action(Action::use_move(1).at(-1))
+                   .use_move(1).build(),
+                   Err(ChoiceError::InvalidTarget { position: 0, target: -1 }));
+        assert_eq!(Choice::builder(&request).action(Action::use_move(1).at(1)
+                   .with(Gimmick::Dynamax)).use_move(1).build(),
+                   Err(ChoiceError::InvalidGimmick {
+                       position: 0,
+                       gimmick: Gimmick::Dynamax,
+                   }));
+        assert_eq!(Choice::builder(&request).pass().use_move(1).build(),
+                   Err(ChoiceError::InvalidAction { position: 0 }));
+        assert_eq!(Choice::builder(&request).use_move(1).build(),
+                   Err(ChoiceError::ActionCount { expected: 2, actual: 1 }));
+    }
+
+    #[test]
+    fn team_test() {
+        let request = Request::parse(r#"{"teamPreview": true, "side": {
+            "pokemon": [
+                {"ident": "p1: A", "details": "A", "condition": "100/100"},
+                {"ident": "p1: B", "details": "B", "condition": "100/100"}
+            ]}}"#).unwrap();
+        assert_eq!(Choice::Team(vec![2, 1]).validate(&request), Ok(()));
+        assert_eq!(Choice::Team(vec![2, 2]).validate(&request),
+                   Err(ChoiceError::InvalidTeam));
+        assert_eq!(Choice::Team(vec![3]).validate(&request),
+                   Err(ChoiceError::InvalidTeam));
+        assert_eq!(Choice::builder(&request).use_move(1).build(),
+                   Err(ChoiceError::WrongKind));
+    }
+}
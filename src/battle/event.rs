@@ -0,0 +1,439 @@
+use std::fmt;
+
+use protocol::{ParseError, Params};
+
+/// A side of a battle, `p1` to `p4`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Side {
+    P1,
+    P2,
+    P3,
+    P4,
+}
+
+impl Side {
+    /// Parses a side from the start of an identifier like `p1` or `p2a`.
+    pub fn parse(s: &str) -> Option<Side> {
+        match s.get(..2) {
+            Some("p1") => Some(Side::P1),
+            Some("p2") => Some(Side::P2),
+            Some("p3") => Some(Side::P3),
+            Some("p4") => Some(Side::P4),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Side::P1 => write!(f, "p1"),
+            Side::P2 => write!(f, "p2"),
+            Side::P3 => write!(f, "p3"),
+            Side::P4 => write!(f, "p4"),
+        }
+    }
+}
+
+/// A Pokemon as identified in the protocol, e.g. `p1a: Pikachu`. The
+/// position is the letter after the side, and is missing for Pokemon that
+/// are not active.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PokemonId {
+    pub side: Side,
+    pub position: Option<char>,
+    pub name: String,
+}
+
+impl PokemonId {
+    pub fn parse(s: &str) -> Option<PokemonId> {
+        let side = Side::parse(s)?;
+        let (slot, name) = match s.find(": ") {
+            Some(i) => (&s[2..i], &s[i + 2..]),
+            None => (&s[2..], ""),
+        };
+        Some(PokemonId {
+            side,
+            position: slot.chars().next(),
+            name: name.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for PokemonId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some(p) => write!(f, "{}{}: {}", self.side, p, self.name),
+            None => write!(f, "{}: {}", self.side, self.name),
+        }
+    }
+}
+
+/// The details of a Pokemon as sent on switch, e.g.
+/// `Pikachu, L84, F, shiny, tera:Electric`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Details {
+    pub species: String,
+    pub level: u32,
+    pub gender: Option<char>,
+    pub shiny: bool,
+    pub tera_type: Option<String>,
+}
+
+impl Details {
+    pub fn parse(s: &str) -> Option<Details> {
+        let mut parts = s.split(", ");
+        let species = parts.next().filter(|p| !p.is_empty())?;
+        let mut details = Details {
+            species: species.to_string(),
+            level: 100,
+            gender: None,
+            shiny: false,
+            tera_type: None,
+        };
+
+        for part in parts {
+            if part.starts_with('L') {
+                details.level = part[1..].parse().ok()?;
+            } else if part == "M" || part == "F" {
+                details.gender = part.chars().next();
+            } else if part == "shiny" {
+                details.shiny = true;
+            } else if part.starts_with("tera:") {
+                details.tera_type = Some(part[5..].to_string());
+            }
+        }
+        Some(details)
+    }
+}
+
+/// A non-volatile status condition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Status {
+    Burn,
+    Freeze,
+    Paralysis,
+    Poison,
+    Toxic,
+    Sleep,
+}
+
+impl Status {
+    pub fn parse(s: &str) -> Option<Status> {
+        match s {
+            "brn" => Some(Status::Burn),
+            "frz" => Some(Status::Freeze),
+            "par" => Some(Status::Paralysis),
+            "psn" => Some(Status::Poison),
+            "tox" => Some(Status::Toxic),
+            "slp" => Some(Status::Sleep),
+            _ => None,
+        }
+    }
+}
+
+/// The HP and status of a Pokemon, e.g. `45/100 par` or `0 fnt`. The HP of
+/// opposing Pokemon is usually out of 100.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub hp: u32,
+    pub max_hp: u32,
+    pub status: Option<Status>,
+    pub fainted: bool,
+}
+
+impl Condition {
+    pub fn parse(s: &str) -> Option<Condition> {
+        let mut parts = s.split(' ');
+        let hp = parts.next()?;
+        let (hp, max_hp) = match hp.find('/') {
+            Some(i) => (hp[..i].parse().ok()?, hp[i + 1..].parse().ok()?),
+            None => (hp.parse().ok()?, 0),
+        };
+
+        let mut condition = Condition {
+            hp,
+            max_hp,
+            status: None,
+            fainted: false,
+        };
+        match parts.next() {
+            Some("fnt") => condition.fainted = true,
+            Some(status) => condition.status = Some(Status::parse(status)?),
+            None => (),
+        }
+        Some(condition)
+    }
+
+    /// Returns the HP left as a fraction of the maximum.
+    pub fn fraction(&self) -> f64 {
+        if self.max_hp == 0 {
+            0.0
+        } else {
+            self.hp as f64 / self.max_hp as f64
+        }
+    }
+}
+
+/// An event of the battle protocol, sent in `>battle-...` rooms.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BattleEvent {
+    /// `|player|PLAYER|USERNAME|AVATAR|RATING`
+    Player { side: Side, name: String, avatar: String, rating: Option<u32> },
+    /// `|teamsize|PLAYER|NUMBER`
+    TeamSize { side: Side, size: u32 },
+    /// `|gametype|GAMETYPE`
+    GameType(String),
+    /// `|gen|GENNUM`
+    Gen(u32),
+    /// `|tier|FORMATNAME`
+    Tier(String),
+    /// `|rule|RULE: DESCRIPTION`
+    Rule(String),
+    /// `|start`
+    Start,
+    /// `|turn|NUMBER`
+    Turn(u32),
+    /// `|upkeep`, sent at the end of a turn.
+    Upkeep,
+    /// `|move|POKEMON|MOVE|TARGET`
+    Move { pokemon: PokemonId, name: String, target: Option<PokemonId> },
+    /// `|switch|POKEMON|DETAILS|HP STATUS`
+    Switch { pokemon: PokemonId, details: Details, condition: Condition },
+    /// `|drag|POKEMON|DETAILS|HP STATUS`
+    Drag { pokemon: PokemonId, details: Details, condition: Condition },
+    /// `|faint|POKEMON`
+    Faint(PokemonId),
+    /// `|-damage|POKEMON|HP STATUS`, with the `[from]` tag if any.
+    Damage { pokemon: PokemonId, condition: Condition, from: Option<String> },
+    /// `|-heal|POKEMON|HP STATUS`, with the `[from]` tag if any.
+    Heal { pokemon: PokemonId, condition: Condition, from: Option<String> },
+    /// `|-status|POKEMON|STATUS`
+    Status { pokemon: PokemonId, status: Status },
+    /// `|-curestatus|POKEMON|STATUS`
+    CureStatus { pokemon: PokemonId, status: Status },
+    /// `|-boost|POKEMON|STAT|AMOUNT`, or `|-unboost|...` with a negative
+    /// amount.
+    Boost { pokemon: PokemonId, stat: String, amount: i32 },
+    /// `|-clearboost|POKEMON`
+    ClearBoost(PokemonId),
+    /// `|-item|POKEMON|ITEM`
+    Item { pokemon: PokemonId, item: String },
+    /// `|-enditem|POKEMON|ITEM`
+    EndItem { pokemon: PokemonId, item: String },
+    /// `|-ability|POKEMON|ABILITY`
+    Ability { pokemon: PokemonId, ability: String },
+    /// `|-weather|WEATHER`, where `upkeep` is true if it continues from the
+    /// last turn. The weather is `none` when it ends.
+    Weather { weather: String, upkeep: bool },
+    /// `|-fieldstart|CONDITION`
+    FieldStart(String),
+    /// `|-fieldend|CONDITION`
+    FieldEnd(String),
+    /// `|-sidestart|SIDE|CONDITION`
+    SideStart { side: Side, condition: String },
+    /// `|-sideend|SIDE|CONDITION`
+    SideEnd { side: Side, condition: String },
+    /// `|win|USER`
+    Win(String),
+    /// `|tie`
+    Tie,
+}
+
+/// Removes the `move: ` style prefix from an effect like `move: Stealth Rock`.
+fn effect(s: &str) -> String {
+    match s.find(": ") {
+        Some(i) => s[i + 2..].to_string(),
+        None => s.to_string(),
+    }
+}
+
+/// Returns the value of a `[TAG] VALUE` parameter, if one is given.
+fn tag(p: &Params, index: usize, name: &str) -> Option<String> {
+    p.params.iter().skip(index)
+        .filter(|t| t.starts_with(name))
+        .map(|t| effect(t[name.len()..].trim()))
+        .next()
+}
+
+impl BattleEvent {
+    /// Parses a line of the battle protocol. Returns `None` for lines that
+    /// are not battle events.
+    pub fn parse(line: &str) -> Result<Option<BattleEvent>, ParseError> {
+        if !line.starts_with('|') {
+            return Ok(None);
+        }
+
+        let p = Params::new(line);
+        let event = match p.command {
+            "player" => BattleEvent::Player {
+                side: p.with(0, Side::parse)?,
+                name: p.optional(1).into_owned(),
+                avatar: p.optional(2).into_owned(),
+                rating: p.get(3).ok().and_then(|r| r.parse().ok()),
+            },
+            "teamsize" => BattleEvent::TeamSize {
+                side: p.with(0, Side::parse)?,
+                size: p.number(1)?,
+            },
+            "gametype" => BattleEvent::GameType(p.string(0)?.into_owned()),
+            "gen" => BattleEvent::Gen(p.number(0)?),
+            "tier" => BattleEvent::Tier(p.string(0)?.into_owned()),
+            "rule" => BattleEvent::Rule(p.rest(0)?.into_owned()),
+            "start" => BattleEvent::Start,
+            "turn" => BattleEvent::Turn(p.number(0)?),
+            "upkeep" => BattleEvent::Upkeep,
+            "move" => BattleEvent::Move {
+                pokemon: p.with(0, PokemonId::parse)?,
+                name: p.string(1)?.into_owned(),
+                target: p.get(2).ok().and_then(PokemonId::parse),
+            },
+            "switch" => BattleEvent::Switch {
+                pokemon: p.with(0, PokemonId::parse)?,
+                details: p.with(1, Details::parse)?,
+                condition: p.with(2, Condition::parse)?,
+            },
+            "drag" => BattleEvent::Drag {
+                pokemon: p.with(0, PokemonId::parse)?,
+                details: p.with(1, Details::parse)?,
+                condition: p.with(2, Condition::parse)?,
+            },
+            "faint" => BattleEvent::Faint(p.with(0, PokemonId::parse)?),
+            "-damage" => BattleEvent::Damage {
+                pokemon: p.with(0, PokemonId::parse)?,
+                condition: p.with(1, Condition::parse)?,
+                from: tag(&p, 2, "[from]"),
+            },
+            "-heal" => BattleEvent::Heal {
+                pokemon: p.with(0, PokemonId::parse)?,
+                condition: p.with(1, Condition::parse)?,
+                from: tag(&p, 2, "[from]"),
+            },
+            "-status" => BattleEvent::Status {
+                pokemon: p.with(0, PokemonId::parse)?,
+                status: p.with(1, Status::parse)?,
+            },
+            "-curestatus" => BattleEvent::CureStatus {
+                pokemon: p.with(0, PokemonId::parse)?,
+                status: p.with(1, Status::parse)?,
+            },
+            "-boost" => BattleEvent::Boost {
+                pokemon: p.with(0, PokemonId::parse)?,
+                stat: p.string(1)?.into_owned(),
+                amount: p.number(2)?,
+            },
+            "-unboost" => BattleEvent::Boost {
+                pokemon: p.with(0, PokemonId::parse)?,
+                stat: p.string(1)?.into_owned(),
+                amount: -p.number::<i32>(2)?,
+            },
+            "-clearboost" => BattleEvent::ClearBoost(p.with(0, PokemonId::parse)?),
+            "-item" => BattleEvent::Item {
+                pokemon: p.with(0, PokemonId::parse)?,
+                item: p.string(1)?.into_owned(),
+            },
+            "-enditem" => BattleEvent::EndItem {
+                pokemon: p.with(0, PokemonId::parse)?,
+                item: p.string(1)?.into_owned(),
+            },
+            "-ability" => BattleEvent::Ability {
+                pokemon: p.with(0, PokemonId::parse)?,
+                ability: p.string(1)?.into_owned(),
+            },
+            "-weather" => BattleEvent::Weather {
+                weather: p.string(0)?.into_owned(),
+                upkeep: p.params.iter().any(|t| *t == "[upkeep]"),
+            },
+            "-fieldstart" => BattleEvent::FieldStart(effect(p.get(0)?)),
+            "-fieldend" => BattleEvent::FieldEnd(effect(p.get(0)?)),
+            "-sidestart" => BattleEvent::SideStart {
+                side: p.with(0, Side::parse)?,
+                condition: effect(p.get(1)?),
+            },
+            "-sideend" => BattleEvent::SideEnd {
+                side: p.with(0, Side::parse)?,
+                condition: effect(p.get(1)?),
+            },
+            "win" => BattleEvent::Win(p.rest(0)?.into_owned()),
+            "tie" => BattleEvent::Tie,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BattleEvent, Condition, Details, PokemonId, Side, Status};
+
+    fn pikachu() -> PokemonId {
+        PokemonId {
+            side: Side::P1,
+            position: Some('a'),
+            name: "Pikachu".to_string(),
+        }
+    }
+
+    #[test]
+    fn identifier_test() {
+        assert_eq!(PokemonId::parse("p1a: Pikachu"), Some(pikachu()));
+        assert_eq!(PokemonId::parse("p2: Mr. Mime: Galar").unwrap().name,
+                   "Mr. Mime: Galar");
+        assert_eq!(PokemonId::parse("p2: Mr. Mime").unwrap().position, None);
+        assert_eq!(PokemonId::parse("Pikachu"), None);
+        assert_eq!(pikachu().to_string(), "p1a: Pikachu");
+    }
+
+    #[test]
+    fn condition_test() {
+        assert_eq!(Condition::parse("45/100 par"), Some(Condition {
+            hp: 45,
+            max_hp: 100,
+            status: Some(Status::Paralysis),
+            fainted: false,
+        }));
+        let fainted = Condition::parse("0 fnt").unwrap();
+        assert!(fainted.fainted);
+        assert_eq!(fainted.fraction(), 0.0);
+        assert_eq!(Condition::parse("150/300").unwrap().fraction(), 0.5);
+        assert_eq!(Condition::parse("45/100 xyz"), None);
+    }
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(BattleEvent::parse("|switch|p1a: Pikachu|Pikachu, L84, M|100/100"),
+                   Ok(Some(BattleEvent::Switch {
+                       pokemon: pikachu(),
+                       details: Details {
+                           species: "Pikachu".to_string(),
+                           level: 84,
+                           gender: Some('M'),
+                           shiny: false,
+                           tera_type: None,
+                       },
+                       condition: Condition::parse("100/100").unwrap(),
+                   })));
+        assert_eq!(BattleEvent::parse("|-damage|p1a: Pikachu|50/100|[from] item: Life Orb"),
+                   Ok(Some(BattleEvent::Damage {
+                       pokemon: pikachu(),
+                       condition: Condition::parse("50/100").unwrap(),
+                       from: Some("Life Orb".to_string()),
+                   })));
+        assert_eq!(BattleEvent::parse("|-unboost|p1a: Pikachu|atk|2"),
+                   Ok(Some(BattleEvent::Boost {
+                       pokemon: pikachu(),
+                       stat: "atk".to_string(),
+                       amount: -2,
+                   })));
+        assert_eq!(BattleEvent::parse("|-sidestart|p2: Bob|move: Stealth Rock"),
+                   Ok(Some(BattleEvent::SideStart {
+                       side: Side::P2,
+                       condition: "Stealth Rock".to_string(),
+                   })));
+        assert_eq!(BattleEvent::parse("|turn|3"), Ok(Some(BattleEvent::Turn(3))));
+        assert_eq!(BattleEvent::parse("|upkeep"), Ok(Some(BattleEvent::Upkeep)));
+        assert_eq!(BattleEvent::parse("|c|+user|hi"), Ok(None));
+        assert!(BattleEvent::parse("|switch|Pikachu|Pikachu|100/100").is_err());
+    }
+}
@@ -0,0 +1,15 @@
+//! The battle protocol, sent in `>battle-...` rooms, and playing battles
+//! with a `BattleAgent`.
+
+pub use self::agent::{BattleAgent, RandomAgent};
+pub use self::choice::{Action, Choice, ChoiceBuilder, ChoiceError, Gimmick};
+pub use self::event::{BattleEvent, Condition, Details, PokemonId, Side, Status};
+pub use self::request::{ActiveRequest, MoveRequest, PokemonRequest, Request,
+                        SideRequest};
+pub use self::state::{BattleState, PokemonState, SideState};
+
+mod agent;
+mod choice;
+mod event;
+mod request;
+mod state;
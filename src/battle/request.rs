@@ -0,0 +1,167 @@
+use serde::{Deserialize, Deserializer};
+use serde_json::{self, Value};
+
+use super::event::{Condition, PokemonId};
+
+/// A decision request, sent to a player as `|request|JSON`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    /// What each active Pokemon can do, in position order. Empty when the
+    /// player only has to switch or pick a team.
+    #[serde(default)]
+    pub active: Vec<ActiveRequest>,
+    #[serde(default)]
+    pub side: SideRequest,
+    /// For each position, whether the Pokemon there has to be switched out.
+    #[serde(default)]
+    pub force_switch: Vec<bool>,
+    #[serde(default)]
+    pub team_preview: bool,
+    /// True if the player has nothing to decide and waits for the opponent.
+    #[serde(default)]
+    pub wait: bool,
+    /// The id of the request, sent back with the choice.
+    #[serde(default)]
+    pub rqid: Option<u64>,
+}
+
+/// The options of one active Pokemon.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveRequest {
+    #[serde(default)]
+    pub moves: Vec<MoveRequest>,
+    #[serde(default, deserialize_with = "truthy")]
+    pub trapped: bool,
+    #[serde(default, deserialize_with = "truthy")]
+    pub maybe_trapped: bool,
+    #[serde(default, deserialize_with = "truthy")]
+    pub can_terastallize: bool,
+    #[serde(default, deserialize_with = "truthy")]
+    pub can_mega_evo: bool,
+    #[serde(default, deserialize_with = "truthy")]
+    pub can_dynamax: bool,
+    #[serde(default, deserialize_with = "truthy")]
+    pub can_z_move: bool,
+}
+
+/// A move of an active Pokemon.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveRequest {
+    #[serde(rename = "move")]
+    pub name: String,
+    pub id: String,
+    #[serde(default)]
+    pub pp: u32,
+    #[serde(default)]
+    pub maxpp: u32,
+    /// The move's target type, e.g. `normal` or `allAdjacentFoes`.
+    #[serde(default)]
+    pub target: String,
+    #[serde(default, deserialize_with = "truthy")]
+    pub disabled: bool,
+}
+
+/// The player's side, with their whole team.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct SideRequest {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub pokemon: Vec<PokemonRequest>,
+}
+
+/// A Pokemon on the player's team. Active Pokemon come first.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PokemonRequest {
+    /// The identifier, e.g. `p1: Pikachu`.
+    pub ident: String,
+    pub details: String,
+    /// The HP and status, e.g. `45/100 par`.
+    pub condition: String,
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub moves: Vec<String>,
+    #[serde(default)]
+    pub base_ability: String,
+    #[serde(default)]
+    pub item: String,
+    #[serde(default)]
+    pub tera_type: String,
+}
+
+impl Request {
+    pub fn parse(json: &str) -> ::Result<Request> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+impl PokemonRequest {
+    pub fn id(&self) -> Option<PokemonId> {
+        PokemonId::parse(&self.ident)
+    }
+
+    pub fn condition(&self) -> Option<Condition> {
+        Condition::parse(&self.condition)
+    }
+
+    pub fn is_fainted(&self) -> bool {
+        self.condition.ends_with(" fnt")
+    }
+}
+
+/// Reads flags that are sent either as booleans or, when the reason is
+/// shown, as strings.
+fn truthy<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Bool(b) => b,
+        Value::String(s) => !s.is_empty(),
+        Value::Null => false,
+        _ => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Request;
+
+    #[test]
+    fn parse_test() {
+        let request = Request::parse(r#"{
+            "active": [{"canTerastallize": "Electric", "moves": [
+                {"move": "Thunderbolt", "id": "thunderbolt", "pp": 23,
+                 "maxpp": 24, "target": "normal", "disabled": false},
+                {"move": "Protect", "id": "protect", "pp": 16, "maxpp": 16,
+                 "target": "self", "disabled": "Imprison"}
+            ]}],
+            "side": {"name": "Alice", "id": "p1", "pokemon": [
+                {"ident": "p1: Pikachu", "details": "Pikachu, L84, M",
+                 "condition": "100/100", "active": true,
+                 "moves": ["thunderbolt", "protect"],
+                 "baseAbility": "static", "item": "lightball"},
+                {"ident": "p1: Gyarados", "details": "Gyarados, L80, F",
+                 "condition": "0 fnt", "active": false,
+                 "moves": ["waterfall"]}
+            ]},
+            "rqid": 3
+        }"#).unwrap();
+
+        assert_eq!(request.rqid, Some(3));
+        assert!(!request.team_preview);
+        assert!(!request.active[0].moves[0].disabled);
+        assert!(request.active[0].moves[1].disabled);
+        assert!(request.active[0].can_terastallize);
+        assert!(!request.active[0].can_dynamax);
+        assert_eq!(request.side.pokemon[0].base_ability, "static");
+        assert!(request.side.pokemon[1].is_fainted());
+
+        let request = Request::parse(r#"{"wait": true, "side": {}}"#).unwrap();
+        assert!(request.wait);
+    }
+}
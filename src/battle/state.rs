@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::event::{BattleEvent, Condition, Details, PokemonId, Side};
+use super::request::Request;
+
+/// What is known about a Pokemon in a battle. For opposing Pokemon only what
+/// has been revealed so far is known.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PokemonState {
+    /// The nickname the Pokemon is identified by.
+    pub name: String,
+    pub details: Details,
+    pub condition: Condition,
+    /// The position of the Pokemon on the field, if it is active.
+    pub position: Option<char>,
+    /// Stat boosts, from -6 to 6. Reset when the Pokemon switches out.
+    pub boosts: BTreeMap<String, i32>,
+    /// Moves in the order they were revealed.
+    pub moves: Vec<String>,
+    pub item: Option<String>,
+    pub ability: Option<String>,
+}
+
+impl PokemonState {
+    fn new(name: &str, details: Details, condition: Condition) -> PokemonState {
+        PokemonState {
+            name: name.to_string(),
+            details,
+            condition,
+            position: None,
+            boosts: BTreeMap::new(),
+            moves: Vec::new(),
+            item: None,
+            ability: None,
+        }
+    }
+
+    /// Returns the HP left as a fraction of the maximum.
+    pub fn hp_fraction(&self) -> f64 {
+        self.condition.fraction()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.position.is_some()
+    }
+
+    pub fn is_fainted(&self) -> bool {
+        self.condition.fainted
+    }
+}
+
+/// What is known about one side of a battle.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SideState {
+    /// The name of the player.
+    pub player: String,
+    pub rating: Option<u32>,
+    pub team_size: u32,
+    /// Pokemon in the order they were first seen.
+    pub pokemon: Vec<PokemonState>,
+    /// Side conditions like `Stealth Rock` or `Reflect`.
+    pub conditions: BTreeSet<String>,
+}
+
+impl SideState {
+    /// Returns the active Pokemon, ordered by position.
+    pub fn active(&self) -> Vec<&PokemonState> {
+        let mut active: Vec<&PokemonState> = self.pokemon.iter()
+            .filter(|p| p.is_active())
+            .collect();
+        active.sort_by_key(|p| p.position);
+        active
+    }
+
+    fn find(&self, id: &PokemonId) -> Option<usize> {
+        self.pokemon.iter().position(|p| p.name == id.name)
+    }
+}
+
+/// The state of a battle, kept up to date from the `BattleEvent`s of its
+/// room.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BattleState {
+    pub room: String,
+    pub gen: u32,
+    pub tier: String,
+    pub game_type: String,
+    pub turn: u32,
+    pub sides: BTreeMap<Side, SideState>,
+    /// The current weather, if any.
+    pub weather: Option<String>,
+    /// Field conditions like `Electric Terrain` or `Trick Room`.
+    pub field: BTreeSet<String>,
+    pub ended: bool,
+    /// The name of the winner, if the battle was won.
+    pub winner: Option<String>,
+    /// The last request sent to the bot, if it is playing.
+    pub request: Option<Request>,
+}
+
+impl BattleState {
+    pub fn new(room: &str) -> BattleState {
+        BattleState { room: room.to_string(), ..BattleState::default() }
+    }
+
+    /// Returns a side of the battle, if anything is known about it.
+    pub fn side(&self, side: Side) -> Option<&SideState> {
+        self.sides.get(&side)
+    }
+
+    /// Returns what is known about a Pokemon.
+    pub fn pokemon(&self, id: &PokemonId) -> Option<&PokemonState> {
+        let side = self.sides.get(&id.side)?;
+        side.find(id).map(|i| &side.pokemon[i])
+    }
+
+    fn pokemon_mut(&mut self, id: &PokemonId) -> Option<&mut PokemonState> {
+        let side = self.sides.get_mut(&id.side)?;
+        match side.find(id) {
+            Some(i) => Some(&mut side.pokemon[i]),
+            None => None,
+        }
+    }
+
+    fn switch_in(&mut self, id: &PokemonId, details: &Details,
+                 condition: &Condition) {
+        let side = self.sides.entry(id.side).or_insert_with(SideState::default);
+
+        // Whatever was in the position switches out and loses its boosts
+        for p in side.pokemon.iter_mut().filter(|p| p.position == id.position) {
+            p.position = None;
+            p.boosts.clear();
+        }
+
+        let i = match side.find(id) {
+            Some(i) => i,
+            None => {
+                side.pokemon.push(PokemonState::new(
+                    &id.name, details.clone(), condition.clone()));
+                side.pokemon.len() - 1
+            },
+        };
+        let pokemon = &mut side.pokemon[i];
+        pokemon.details = details.clone();
+        pokemon.condition = condition.clone();
+        pokemon.position = id.position;
+    }
+
+    /// Updates the state with an event.
+    pub fn apply(&mut self, event: &BattleEvent) {
+        match *event {
+            BattleEvent::Player { side, ref name, rating, .. } => {
+                let side = self.sides.entry(side)
+                    .or_insert_with(SideState::default);
+                side.player = name.clone();
+                side.rating = rating;
+            },
+            BattleEvent::TeamSize { side, size } => {
+                self.sides.entry(side)
+                    .or_insert_with(SideState::default)
+                    .team_size = size;
+            },
+            BattleEvent::GameType(ref game_type) =>
+                self.game_type = game_type.clone(),
+            BattleEvent::Gen(gen) => self.gen = gen,
+            BattleEvent::Tier(ref tier) => self.tier = tier.clone(),
+            BattleEvent::Turn(turn) => self.turn = turn,
+            BattleEvent::Move { ref pokemon, ref name, .. } => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    if !p.moves.contains(name) {
+                        p.moves.push(name.clone());
+                    }
+                }
+            },
+            BattleEvent::Switch { ref pokemon, ref details, ref condition } |
+            BattleEvent::Drag { ref pokemon, ref details, ref condition } =>
+                self.switch_in(pokemon, details, condition),
+            BattleEvent::Faint(ref pokemon) => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    p.condition.hp = 0;
+                    p.condition.status = None;
+                    p.condition.fainted = true;
+                }
+            },
+            BattleEvent::Damage { ref pokemon, ref condition, .. } |
+            BattleEvent::Heal { ref pokemon, ref condition, .. } => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    p.condition = condition.clone();
+                }
+            },
+            BattleEvent::Status { ref pokemon, status } => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    p.condition.status = Some(status);
+                }
+            },
+            BattleEvent::CureStatus { ref pokemon, .. } => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    p.condition.status = None;
+                }
+            },
+            BattleEvent::Boost { ref pokemon, ref stat, amount } => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    let boost = p.boosts.entry(stat.clone()).or_insert(0);
+                    *boost = (*boost + amount).max(-6).min(6);
+                }
+            },
+            BattleEvent::ClearBoost(ref pokemon) => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    p.boosts.clear();
+                }
+            },
+            BattleEvent::Item { ref pokemon, ref item } => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    p.item = Some(item.clone());
+                }
+            },
+            BattleEvent::EndItem { ref pokemon, .. } => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    p.item = None;
+                }
+            },
+            BattleEvent::Ability { ref pokemon, ref ability } => {
+                if let Some(p) = self.pokemon_mut(pokemon) {
+                    p.ability = Some(ability.clone());
+                }
+            },
+            BattleEvent::Weather { ref weather, .. } => {
+                self.weather = if weather == "none" {
+                    None
+                } else {
+                    Some(weather.clone())
+                };
+            },
+            BattleEvent::FieldStart(ref condition) => {
+                self.field.insert(condition.clone());
+            },
+            BattleEvent::FieldEnd(ref condition) => {
+                self.field.remove(condition);
+            },
+            BattleEvent::SideStart { side, ref condition } => {
+                self.sides.entry(side)
+                    .or_insert_with(SideState::default)
+                    .conditions.insert(condition.clone());
+            },
+            BattleEvent::SideEnd { side, ref condition } => {
+                if let Some(side) = self.sides.get_mut(&side) {
+                    side.conditions.remove(condition);
+                }
+            },
+            BattleEvent::Win(ref user) => {
+                self.ended = true;
+                self.winner = Some(user.clone());
+            },
+            BattleEvent::Tie => self.ended = true,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BattleState;
+    use battle::event::{BattleEvent, PokemonId, Side, Status};
+
+    fn apply(state: &mut BattleState, lines: &[&str]) {
+        for line in lines {
+            if let Some(event) = BattleEvent::parse(line).unwrap() {
+                state.apply(&event);
+            }
+        }
+    }
+
+    #[test]
+    fn apply_test() {
+        let mut state = BattleState::new("battle-gen9ou-123");
+        apply(&mut state, &[
+            "|player|p1|Alice|1|1500",
+            "|player|p2|Bob|2|",
+            "|gen|9",
+            "|switch|p1a: Pikachu|Pikachu, L84, M|100/100",
+            "|switch|p2a: Gyarados|Gyarados, L80, F|100/100",
+            "|turn|1",
+            "|move|p2a: Gyarados|Dragon Dance|p2a: Gyarados",
+            "|-boost|p2a: Gyarados|atk|1",
+            "|move|p1a: Pikachu|Thunderbolt|p2a: Gyarados",
+            "|-damage|p2a: Gyarados|20/100 par",
+            "|-sidestart|p2: Bob|move: Stealth Rock",
+            "|-weather|RainDance",
+            "|turn|2",
+        ]);
+
+        let gyarados = PokemonId::parse("p2a: Gyarados").unwrap();
+        assert_eq!(state.gen, 9);
+        assert_eq!(state.turn, 2);
+        assert_eq!(state.weather, Some("RainDance".to_string()));
+        assert_eq!(state.side(Side::P1).unwrap().player, "Alice");
+        assert!(state.side(Side::P2).unwrap().conditions.contains("Stealth Rock"));
+
+        let p = state.pokemon(&gyarados).unwrap();
+        assert_eq!(p.hp_fraction(), 0.2);
+        assert_eq!(p.condition.status, Some(Status::Paralysis));
+        assert_eq!(p.boosts.get("atk"), Some(&1));
+        assert_eq!(p.moves, vec!["Dragon Dance".to_string()]);
+
+        // Switching out clears boosts and frees the position
+        apply(&mut state, &[
+            "|switch|p2a: Dragonite|Dragonite, L78, M|100/100",
+            "|win|Alice",
+        ]);
+        let p = state.pokemon(&gyarados).unwrap();
+        assert!(!p.is_active());
+        assert!(p.boosts.is_empty());
+        assert_eq!(state.side(Side::P2).unwrap().active()[0].name, "Dragonite");
+        assert!(state.ended);
+        assert_eq!(state.winner, Some("Alice".to_string()));
+    }
+}
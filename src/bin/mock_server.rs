@@ -0,0 +1,329 @@
+//! A minimal Pokemon Showdown server for testing bots locally.
+//!
+//! Usage: `showdown_mock_server [ADDRESS] [SCRIPT] [LOGIN_ADDRESS]`
+//!
+//! The server listens on `ADDRESS` (default `127.0.0.1:8000`) and accepts
+//! websocket connections at `/showdown/websocket`. Every client gets a
+//! `|challstr|`, may log in with any `/trn`, and can `/join` and `/leave`
+//! rooms. Chat lines from `SCRIPT` are replayed when the client logs in or
+//! joins the room they are listed under.
+//!
+//! If `LOGIN_ADDRESS` is given, a stand-in for the login server is served
+//! there over plain HTTP. It hands out an assertion for any login, unless
+//! the password is `wrong`, so set `login_server` to
+//! `http://LOGIN_ADDRESS/action.php` in the bot config.
+
+extern crate showdown;
+extern crate env_logger;
+#[macro_use]
+extern crate log;
+extern crate rand;
+extern crate time;
+extern crate websocket;
+
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use rand::Rng;
+use showdown::helpers::sanitize;
+use websocket::Message;
+use websocket::client::sync::Client;
+use websocket::message::Type;
+use websocket::sync::Server;
+
+static WEBSOCKET_PATH: &str = "/showdown/websocket";
+
+/// Chat lines to replay, keyed by room id. Lines listed before any `>ROOM`
+/// header are kept under the empty key and sent right after login.
+///
+/// `{now}` in a line is replaced by the current UNIX timestamp, so that
+/// scripted `|c:|` lines are not older than the bot's login time.
+struct Script {
+    rooms: HashMap<String, Vec<String>>,
+}
+
+impl Script {
+    fn empty() -> Script {
+        Script { rooms: HashMap::new() }
+    }
+
+    fn load(path: &str) -> io::Result<Script> {
+        let f = File::open(path)?;
+        let mut rooms = HashMap::new();
+        let mut room = String::new();
+
+        for line in BufReader::new(f).lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('>') {
+                room = sanitize(&line[1..]);
+                continue;
+            }
+            rooms.entry(room.clone()).or_insert_with(Vec::new).push(line);
+        }
+
+        Ok(Script { rooms })
+    }
+
+    fn lines(&self, room: &str) -> Vec<String> {
+        let now = now().to_string();
+        match self.rooms.get(room) {
+            Some(lines) => lines.iter().map(|l| l.replace("{now}", &now)).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// The state of one connected client.
+struct Connection {
+    client: Client<TcpStream>,
+    script: Arc<Script>,
+    name: String,
+    rooms: BTreeSet<String>,
+}
+
+impl Connection {
+    fn new(client: Client<TcpStream>, script: Arc<Script>) -> Connection {
+        let guest = rand::thread_rng().gen_range(1000, 100000);
+        Connection {
+            client,
+            script,
+            name: format!("Guest {}", guest),
+            rooms: BTreeSet::new(),
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        self.send(format!("|updateuser| {}|0|1", self.name))?;
+        self.send(format!("|challstr|4|{}", challstr()))?;
+
+        loop {
+            let message: Message = match self.client.recv_message() {
+                Ok(m) => m,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other,
+                                                    format!("{:?}", e))),
+            };
+
+            match message.opcode {
+                Type::Close => {
+                    let _ = self.client.send_message(&Message::close());
+                    return Ok(());
+                },
+                Type::Ping => {
+                    self.send_message(&Message::pong(message.payload))?;
+                },
+                Type::Text => {
+                    let text = String::from_utf8_lossy(&message.payload)
+                        .into_owned();
+                    info!("<< {}", text);
+                    self.handle(&text)?;
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// Handles a `ROOM|MESSAGE` frame from the client.
+    fn handle(&mut self, text: &str) -> io::Result<()> {
+        let (room, message) = match text.find('|') {
+            Some(i) => (sanitize(&text[..i]), &text[i + 1..]),
+            None => (String::new(), text),
+        };
+
+        if !message.starts_with('/') || message.starts_with("//") {
+            if !self.rooms.contains(&room) {
+                return Ok(());
+            }
+            let line = format!(">{}\n|c:|{}| {}|{}",
+                               room, now(), self.name, message);
+            return self.send(line);
+        }
+
+        let (command, args) = match message.find(' ') {
+            Some(i) => (&message[1..i], message[i + 1..].trim()),
+            None => (&message[1..], ""),
+        };
+
+        match command {
+            "trn" => {
+                self.name = args.split(',').next().unwrap_or("").to_string();
+                self.send(format!("|updateuser| {}|1|1", self.name))?;
+                for line in self.script.lines("") {
+                    self.send(line)?;
+                }
+                Ok(())
+            },
+            "join" | "j" => {
+                let id = sanitize(args);
+                if id.is_empty() || !self.rooms.insert(id.clone()) {
+                    return Ok(());
+                }
+                let mut lines = vec![
+                    format!(">{}", id),
+                    "|init|chat".to_string(),
+                    format!("|title|{}", args),
+                    format!("|users|1, {}", self.name),
+                    format!("|:|{}", now()),
+                ];
+                lines.extend(self.script.lines(&id));
+                self.send(lines.join("\n"))
+            },
+            "leave" | "part" => {
+                let id = if args.is_empty() { room } else { sanitize(args) };
+                if self.rooms.remove(&id) {
+                    self.send(format!(">{}\n|deinit", id))
+                } else {
+                    Ok(())
+                }
+            },
+            "w" | "msg" | "pm" | "whisper" => {
+                let mut parts = args.splitn(2, ',');
+                let to = parts.next().unwrap_or("").trim();
+                let body = parts.next().unwrap_or("").trim();
+                self.send(format!("|pm| {}| {}|{}", self.name, to, body))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn send(&mut self, text: String) -> io::Result<()> {
+        info!(">> {}", text);
+        self.send_message(&Message::text(text))
+    }
+
+    fn send_message(&mut self, message: &Message) -> io::Result<()> {
+        self.client.send_message(message)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+    }
+}
+
+/// Serves the login server stand-in on `addr`.
+fn serve_login(addr: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("Login server listening on http://{}/action.php", addr);
+
+    for stream in listener.incoming() {
+        match stream.and_then(answer_login) {
+            Ok(()) => (),
+            Err(e) => error!("Login server: {:?}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Answers a single `action.php` request.
+fn answer_login(stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            break;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        let lower = header.to_lowercase();
+        if lower.starts_with("content-length:") {
+            content_length = lower[15..].trim().parse().unwrap_or(0);
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body).into_owned();
+    info!("<< POST {}", body);
+
+    let params: HashMap<&str, &str> = body.split('&')
+        .filter_map(|p| {
+            let mut kv = p.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) => Some((k, v)),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let response = match params.get("act") {
+        Some(&"getassertion") => "mock-assertion".to_string(),
+        Some(&"login") if params.get("pass") == Some(&"wrong") =>
+            "]{\"actionsuccess\":false,\"assertion\":\";;Wrong password.\"}"
+                .to_string(),
+        Some(&"login") =>
+            "]{\"actionsuccess\":true,\"assertion\":\"mock-assertion\"}"
+                .to_string(),
+        _ => ";;Unknown action.".to_string(),
+    };
+
+    let mut stream = reader.into_inner();
+    write!(stream, "HTTP/1.1 200 OK\r\n\
+                    Content-Type: text/plain; charset=utf-8\r\n\
+                    Content-Length: {}\r\n\
+                    Connection: close\r\n\r\n{}",
+           response.len(), response)?;
+    stream.flush()
+}
+
+fn now() -> i64 {
+    time::get_time().sec
+}
+
+fn challstr() -> String {
+    let mut rng = rand::thread_rng();
+    (0..128).map(|_| format!("{:x}", rng.gen_range(0, 16))).collect()
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let args: Vec<String> = env::args().collect();
+    let addr = args.get(1).map(|s| &**s).unwrap_or("127.0.0.1:8000");
+    let script = match args.get(2) {
+        Some(path) => Script::load(path).expect("Failed to read script"),
+        None => Script::empty(),
+    };
+    let script = Arc::new(script);
+
+    if let Some(login_addr) = args.get(3).cloned() {
+        thread::spawn(move || {
+            if let Err(e) = serve_login(&login_addr) {
+                error!("Login server: {:?}", e);
+            }
+        });
+    }
+
+    let server = Server::bind(addr).expect("Failed to bind address");
+    info!("Listening on ws://{}{}", addr, WEBSOCKET_PATH);
+
+    for request in server.filter_map(Result::ok) {
+        if request.uri() != WEBSOCKET_PATH {
+            let _ = request.reject();
+            continue;
+        }
+
+        let script = script.clone();
+        thread::spawn(move || {
+            let client = match request.accept() {
+                Ok(c) => c,
+                Err((_, e)) => {
+                    error!("Failed to accept connection: {:?}", e);
+                    return;
+                }
+            };
+
+            info!("Client connected");
+            match Connection::new(client, script).run() {
+                Ok(()) => info!("Client disconnected"),
+                Err(e) => error!("Connection: {:?}", e),
+            }
+        });
+    }
+}
@@ -1,17 +1,30 @@
-﻿use std::thread;
-use std::time::Duration;
+﻿use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
 use std::collections::{BTreeSet, HashMap};
-use std::env;
 use std::io::{Read, stdin};
 use std::path::Path;
-use std::sync::{Arc, Mutex, mpsc};
-
-use serde_json::Value;
-use websocket::{ClientBuilder, Message};
-use websocket::url::Url;
-use websocket::message::Type;
-
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvError, RecvTimeoutError};
+
+use rand::{self, Rng};
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+
+use battle::{BattleAgent, BattleEvent, BattleState, Choice, Request};
+use challenge::{Challenge, ChallengeDecision, ChallengePolicy, Challenges};
+use command::Command;
+use format::FormatCatalog;
+use helpers::sanitize;
+use ladder::{BattleResult, Ladder, Record, Search};
+use outbox::{Outbox, Priority, RateLimiter};
+use query::{Queries, Query, Responder, UserDetails};
 use target::{CacheMap, Room, User};
+use tournament::{Tournament, TournamentEvent, TournamentListener};
+use transport::{Frame, Transport, WebSocketTransport};
+
+/// User details by user id, with the time they were looked up.
+type DetailsCache = HashMap<String, (Instant, UserDetails)>;
 
 /// A `Bot` contains all the bot functionality. It is recommended to only use
 /// one bot even on multiple rooms so that all your messages are throttled.
@@ -22,8 +35,23 @@ pub struct Bot {
     rooms_in: BTreeSet<String>,
     pub user_map: CacheMap<User>,
     pub room_map: CacheMap<Room>,
-    tx: Arc<Mutex<mpsc::Sender<Message<'static>>>>,
-    rx: Arc<Mutex<mpsc::Receiver<Message<'static>>>>,
+    outbox: Arc<Outbox>,
+    closing: Arc<AtomicBool>,
+    logged_in: Arc<AtomicBool>,
+    battles: Arc<Mutex<HashMap<String, BattleState>>>,
+    /// Rooms with a request waiting for a choice, and whether the events
+    /// leading up to it are in.
+    pending: Arc<Mutex<HashMap<String, bool>>>,
+    agent: Arc<Mutex<Option<Box<BattleAgent>>>>,
+    challenges: Arc<Mutex<Challenges>>,
+    policy: Arc<Mutex<Option<Box<ChallengePolicy>>>>,
+    ladder: Arc<Mutex<Ladder>>,
+    formats: Arc<Mutex<FormatCatalog>>,
+    queries: Arc<Mutex<Queries>>,
+    receiver: Arc<Mutex<Option<ThreadId>>>,
+    details: Arc<Mutex<DetailsCache>>,
+    tournaments: Arc<Mutex<HashMap<String, Tournament>>>,
+    listeners: Arc<Mutex<Vec<Box<TournamentListener>>>>,
     plugins: Arc<Mutex<Vec<Arc<Mutex<Box<::Plugin>>>>>>
 }
 
@@ -34,84 +62,169 @@ impl Bot {
     pub fn new<P>(config_path: P) -> ::Result<Bot>
         where P: AsRef<Path>,
     {
-        let (tx, rx) = mpsc::channel();
         Ok(Bot {
             login_time: 0,
             config: ::Config::new(config_path)?,
             rooms_in: BTreeSet::new(),
             user_map: CacheMap::new(),
             room_map: CacheMap::new(),
-            tx: Arc::new(Mutex::new(tx)),
-            rx: Arc::new(Mutex::new(rx)),
+            outbox: Arc::new(Outbox::new()),
+            closing: Arc::new(AtomicBool::new(false)),
+            logged_in: Arc::new(AtomicBool::new(false)),
+            battles: Arc::new(Mutex::new(HashMap::new())),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            agent: Arc::new(Mutex::new(None)),
+            challenges: Arc::new(Mutex::new(Challenges::default())),
+            policy: Arc::new(Mutex::new(None)),
+            ladder: Arc::new(Mutex::new(Ladder::new())),
+            formats: Arc::new(Mutex::new(FormatCatalog::default())),
+            queries: Arc::new(Mutex::new(Queries::new())),
+            receiver: Arc::new(Mutex::new(None)),
+            details: Arc::new(Mutex::new(HashMap::new())),
+            tournaments: Arc::new(Mutex::new(HashMap::new())),
+            listeners: Arc::new(Mutex::new(Vec::new())),
             plugins: Arc::new(Mutex::new(Vec::new()))
         })
     }
 
     /// Initialize the websocket connection to the server. The entrypoint
-    /// method to all Bot functionality and runs the main loop. Returns an
-    /// error if the bot cannot connect to the server.
+    /// method to all Bot functionality and runs the main loop. If the
+    /// connection drops, the bot reconnects with exponential backoff as
+    /// configured in `Config`. Returns an error if the bot cannot connect to
+    /// the server and reconnecting is disabled or has given up.
     pub fn connect(self) -> ::Result<()> {
-        let url = Url::parse(
-            &format!("ws://{}:{}/showdown/websocket",
-            &self.config.host,
-            &self.config.port))?;
+        let console = self.clone();
+
+        debug!("Spawning console thread");
+        thread::spawn(move || {
+            loop {
+                let mut input = String::new();
+                match stdin().read_line(&mut input) {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => (),
+                }
+                match input.trim() {
+                    "/close" => return console.close(),
+                    "/ping" =>
+                        console.send_frame(Frame::Ping(b"PING".to_vec())),
+                    trimmed => console.send(trimmed),
+                }
+            }
+        });
+
+        self.run(WebSocketTransport::connect)
+    }
+
+    /// Runs the bot over connections opened by `connect`, which is called
+    /// again with the config every time the bot reconnects. This is how the
+    /// bot is driven over a `Transport` other than the websocket, such as a
+    /// `MemoryTransport` in tests.
+    pub fn run<T, F>(self, mut connect: F) -> ::Result<()>
+        where T: Transport,
+              F: FnMut(&::Config) -> ::Result<T>,
+    {
+        let closing = self.closing.clone();
+        let logged_in = self.logged_in.clone();
+        let bot = Arc::new(Mutex::new(self));
+
+        let mut attempt = 0;
+        loop {
+            let config = bot.lock().unwrap().config.clone();
+            logged_in.store(false, Ordering::SeqCst);
+            let result = match connect(&config) {
+                Ok(transport) => Ok(Bot::session(&bot, transport)),
+                Err(e) => Err(e),
+            };
+
+            if closing.load(Ordering::SeqCst) {
+                break;
+            }
+
+            match result {
+                Ok(()) => {
+                    warn!("Connection lost");
+                    // A server that drops the bot before it logs in is
+                    // treated like one that cannot be reached
+                    if logged_in.load(Ordering::SeqCst) {
+                        attempt = 0;
+                    }
+                },
+                Err(ref e) => error!("Failed to connect: {}", e),
+            }
+
+            if !config.reconnect {
+                return result;
+            }
+
+            attempt += 1;
+            if config.reconnect_max_attempts > 0 &&
+                attempt > config.reconnect_max_attempts {
+                error!("Giving up after {} reconnect attempts",
+                       config.reconnect_max_attempts);
+                return Err(::Error::ReconnectLimit(
+                        config.reconnect_max_attempts));
+            }
+
+            let delay = backoff_delay(&config, attempt);
+            info!("Reconnecting in {} ms (attempt {})", delay, attempt);
+            thread::sleep(Duration::from_millis(delay));
+
+            // The bot may have been closed while it waited
+            if closing.load(Ordering::SeqCst) {
+                break;
+            }
+
+            // Anything still queued was meant for the dead connection, and
+            // its queries will not be answered
+            let stale = bot.lock().unwrap().outbox.clear();
+            if stale > 0 {
+                debug!("Discarded {} queued messages", stale);
+            }
+            bot.lock().unwrap().queries.lock().unwrap().clear();
+        }
 
-        info!("Connecting to {}", url);
-        let client = ClientBuilder::from_url(&url).connect_insecure()?;
+        info!("Exited");
+        Ok(())
+    }
 
-        info!("Successfully connected");
-        let (mut receiver, mut sender) = client.split()?;
+    /// Runs a single connection to the server until it is closed.
+    fn session<T: Transport>(bot: &Arc<Mutex<Bot>>, transport: T) {
+        let (outbox, mut limiter, plugins, queries, receiver) = {
+            let b = bot.lock().unwrap();
+            let plugins = b.plugins.lock().unwrap().clone();
+            (b.outbox.clone(), RateLimiter::from_config(&b.config), plugins,
+             b.queries.clone(), b.receiver.clone())
+        };
+        let outbox_1 = outbox.clone();
 
-        let self_1 = Arc::new(Mutex::new(self.clone()));
-        let self_2 = self_1.clone();
-        let tx = self_1.lock().unwrap().to_owned().tx;
-        let rx = self_1.lock().unwrap().to_owned().rx;
-        let tx_1 = tx.clone();
-        let plugins = self.plugins.lock().unwrap().clone();
+        let transport_1 = Arc::new(transport);
+        let transport_2 = transport_1.clone();
+        let self_2 = bot.clone();
 
         debug!("Spawning send loop thread");
         let send_loop = thread::spawn(move || {
             loop {
-                let message: Message = match rx.lock().unwrap().recv() {
-                    Ok(m) => m,
-                    Err(e) => {
-                        let _ = sender.send_message(&Message::close());
-                        return Err(e)
-                    }
-                };
+                let frame = outbox_1.pop(&mut limiter);
 
-                // If it's a close message, send it and return
-                match message.opcode {
-                    Type::Close => {
-                        let _ = sender.send_message(&message);
-                        return Ok(());
+                // If it's a close frame, send it and return
+                match frame {
+                    Frame::Close => {
+                        let _ = transport_1.close();
+                        return;
+                    },
+                    Frame::Text(ref text) => {
+                        info!("\x1b[33m↵\x1b[0m {}", text);
                     },
-                    _ => ()
+                    _ => (),
                 }
 
-                // Stringify the payload
-                let text = match String::from_utf8(
-                    message.clone().payload.into_owned()) {
-                    Ok(s) => s,
+                // Send the frame
+                match transport_1.send_frame(frame) {
+                    Ok(()) => (),
                     Err(e) => {
                         error!("Send Loop: {:?}", e);
-                        return Ok(());
-                    }
-                };
-
-                info!("\x1b[33m↵\x1b[0m {}", text);
-
-                // Send the message
-                match sender.send_message(&message) {
-                    Ok(()) => {
-                        thread::sleep(
-                            Duration::from_millis(
-                                self_1.lock().unwrap().config.throttle_ms));
-                    },
-                    Err(e) => {
-                        error!("Send Loop: {:?}", e);
-                        let _ = sender.send_message(&Message::close());
-                        return Ok(());
+                        let _ = transport_1.close();
+                        return;
                     }
                 }
             }
@@ -119,148 +232,596 @@ impl Bot {
 
         debug!("Spawning receive loop thread");
         let recv_loop = thread::spawn(move || {
-            for message in receiver.incoming_messages() {
-                let message: Message = match message {
-                    Ok(m) => m,
-                    Err(e) => {
-                        error!("Receive Loop: {:?}", e);
-                        let _ = tx.lock().unwrap().send(Message::close());
-                        return;
-                    }
-                };
-
-                match message.opcode {
-                    // Send closure when closure is received
-                    Type::Close => {
-                        let _ = tx.lock().unwrap().send(Message::close());
-                        return;
-                    },
+            *receiver.lock().unwrap() = Some(thread::current().id());
+            loop {
+                let payload = match transport_2.recv_frame() {
+                    Ok(Frame::Text(s)) => s,
 
                     // Pong when pinged
-                    Type::Ping => match tx.lock().unwrap()
-                        .send(Message::pong(message.payload)) {
-                        Ok(()) => (),
-                        Err(e) => {
-                            error!("Receive Loop: {:?}", e);
-                            return;
-                        }
+                    Ok(Frame::Ping(data)) => {
+                        outbox.push(Frame::Pong(data));
+                        continue;
                     },
 
-                    // Handle a normal server message
-                    _ => {
-                        let payload = match String::from_utf8(
-                            message.payload.into_owned()) {
-                            Ok(s) => s,
-                            Err(e) => {
-                                error!("Receive Loop: {:?}", e);
-                                return;
-                            }
-                        };
+                    Ok(Frame::Pong(_)) => continue,
 
-                        let mut room = "";
-                        let mut messages: Vec<&str> = payload.trim()
-                            .split("\n")
-                            .collect();
+                    // Send closure when closure is received
+                    Ok(Frame::Close) => {
+                        outbox.push(Frame::Close);
+                        return;
+                    },
 
-                        let first: Vec<u8> = messages[0].bytes()
-                            .take(1)
-                            .collect();
+                    Err(e) => {
+                        error!("Receive Loop: {:?}", e);
+                        outbox.push(Frame::Close);
+                        return;
+                    }
+                };
 
-                        if first[0] == 62u8 {
-                            room = messages[0];
-                            messages = messages[1..].to_vec();
-                        }
+                // Handle a normal server message. If the frame starts with a
+                // ">" then it comes from a room.
+                let frame = payload.trim();
+                let mut lines = frame.split('\n');
+                let header = if frame.starts_with('>') {
+                    lines.next().unwrap_or("")
+                } else {
+                    ""
+                };
+                let room = header.trim_left_matches('>');
 
-                        for message in messages {
-                            info!("\x1b[32m↳\x1b[0m {}", room.to_owned() + message);
+                for line in lines {
+                    info!("\x1b[32m↳\x1b[0m {}{}", header, line);
 
-                            let m = ::Message::from_string(String::from(
-                                    format!("{}\n{}", room, message)), &self_2);
+                    let m = match ::Message::parse(room, line, &self_2) {
+                        Ok(m) => m,
+                        Err(e) => {
+                            warn!("Failed to parse message: {}", e);
+                            continue;
+                        },
+                    };
+
+                    match m.kind {
+                        // Answers are handed over before anything else locks
+                        // the bot, so a caller waiting for one holding the
+                        // lock only stalls the messages after it
+                        ::ServerMessage::QueryResponse {
+                            ref query_type, ref json } =>
+                            Bot::answer(&queries, query_type, json),
+                        _ => (),
+                    }
 
-                            match m.handle(&self_2) {
-                                Err(e) => {
-                                    error!("Failed to handle message: {:?}", e);
-                                    return;
-                                },
-                                _ => (),
-                            }
+                    match m.handle(&self_2) {
+                        Err(e) => {
+                            error!("Failed to handle message: {:?}", e);
+                            outbox.push(Frame::Close);
+                            return;
+                        },
+                        _ => (),
+                    }
 
-                            if !m.payload.is_empty() && m.timestamp >=
-                                self_2.lock().unwrap().login_time ||
-                                m.private {
-                                for p in plugins.iter()
-                                    .filter(|&p| p.lock().unwrap().is_match(&m)) {
-                                    debug!("[plugin] Spawning thread for plugin");
-                                    //thread::spawn(move || {
-                                        p.lock().unwrap().handle(&m);
-                                    //});
-                                }
-                            }
+                    if !m.payload.is_empty() && m.timestamp >=
+                        self_2.lock().unwrap().login_time ||
+                        m.private {
+                        for p in plugins.iter()
+                            .filter(|&p| p.lock().unwrap().is_match(&m)) {
+                            debug!("[plugin] Spawning thread for plugin");
+                            //thread::spawn(move || {
+                                p.lock().unwrap().handle(&m);
+                            //});
                         }
-                    },
-                }
-            }
-        });
-
-        loop {
-            let mut input = String::new();
-            stdin().read_line(&mut input).unwrap();
-            let trimmed = input.trim();
-            let message = match trimmed {
-                "/close" => {
-                    let _ = tx_1.lock().unwrap().send(Message::close());
-                    break;
+                    }
                 }
-                "/ping" => Message::ping(b"PING".to_vec()),
-                _ => Message::text(trimmed.to_string()),
-            };
 
-            match tx_1.lock().unwrap().send(message) {
-                Ok(()) => (),
-                Err(e) => {
-                    error!("Send Loop: {:?}", e);
-                    break;
+                if room.starts_with("battle-") {
+                    Bot::play(&self_2, room);
                 }
             }
-        }
+        });
 
         info!("Waiting for child threads to exit...");
 
         let _ = send_loop.join();
         let _ = recv_loop.join();
-
-        info!("Exited");
-        Ok(())
     }
 
     pub fn register(&self, plugin: Box<::Plugin>) {
         self.plugins.lock().unwrap().push(Arc::new(Mutex::new(plugin)));
     }
 
+    /// Sets the `BattleAgent` that plays the bot's battles.
+    pub fn set_agent(&self, agent: Box<BattleAgent>) {
+        *self.agent.lock().unwrap() = Some(agent);
+    }
+
     /// Send a `String` to the websocket. For convenience, allow any Type that
-    /// implements `Into<String>`.
+    /// implements `Into<String>`. The text is sent as is, so it must not
+    /// contain user input; use `Target::send` for that.
     pub fn send<S: Into<String>>(&self, text: S) {
-        match self.tx.lock().unwrap().send(Message::text(text.into())) {
-            Err(e) => {
-                error!("Failed to send to websocket: {:?}", e);
-                return;
-            }
-            _ => return,
-        }
+        self.send_frame(Frame::Text(text.into()));
+    }
+
+    /// Send a `String` to the websocket ahead of or behind other messages
+    /// depending on its `Priority`.
+    pub fn send_with_priority<S: Into<String>>(&self, text: S,
+                                               priority: Priority) {
+        self.outbox.push_with_priority(Frame::Text(text.into()), priority);
+    }
+
+    /// Send a `Command` from `room`, or globally if it is not a room command,
+    /// with the command's `Priority`.
+    pub fn send_command(&self, command: &Command, room: &str) {
+        self.send_with_priority(command.to_line(room), command.priority());
+    }
+
+    /// Send a `Frame` to the websocket. Pings, pongs and closes are sent
+    /// right away, chat messages are queued as replies.
+    pub fn send_frame(&self, frame: Frame) {
+        self.outbox.push(frame);
+    }
+
+    /// Returns the number of chat messages waiting to be sent. Plugins can
+    /// use this to skip low priority output when the bot is backed up.
+    pub fn queue_depth(&self) -> usize {
+        self.outbox.len()
+    }
+
+    /// Closes the connection once the queued messages are sent and stops
+    /// the bot from reconnecting.
+    pub fn close(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+        self.outbox.close_when_sent();
     }
 
     /// Join a room and update the state given the room name.
     pub fn join_room(&mut self, name: &str) {
         self.room_map.insert(name);
-        self.rooms_in.insert(String::from(name));
-        self.send(format!("|/join {}", name));
+        self.rooms_in.insert(sanitize(name));
+        self.send_command(&Command::Join(name.to_string()), "");
     }
 
     /// Leave a room and update the state given the room name.
     pub fn leave_room(&mut self, name: &str) {
         self.room_map.remove(name);
-        self.rooms_in.remove(name);
-        self.send(format!("|/leave {}", name));
+        self.rooms_in.remove(&sanitize(name));
+        self.send_command(&Command::Leave(name.to_string()), "");
+    }
+
+    /// Join every room returned by `rooms_to_join`.
+    pub fn join_rooms(&mut self) {
+        for r in self.rooms_to_join() {
+            self.join_room(&r);
+        }
+    }
+
+    /// Returns the rooms the bot should be in after logging in: the rooms
+    /// from the config followed by any other room it has joined since. Used
+    /// to rejoin everything after a reconnect.
+    pub fn rooms_to_join(&self) -> Vec<String> {
+        let mut rooms: Vec<String> = Vec::new();
+        for r in self.config.rooms.iter().map(|r| sanitize(r))
+            .chain(self.rooms_in.iter().cloned()) {
+            if !rooms.contains(&r) {
+                rooms.push(r);
+            }
+        }
+        rooms
+    }
+
+    /// Returns the state of the battle in a `battle-...` room the bot is in,
+    /// if it keeps track of it.
+    pub fn battle(&self, room: &str) -> Option<BattleState> {
+        self.battles.lock().unwrap().get(room).cloned()
+    }
+
+    /// Returns true if the bot keeps the state of the battle in a room.
+    pub fn tracks_battle(&self, room: &str) -> bool {
+        self.battles.lock().unwrap().contains_key(room)
+    }
+
+    /// Starts keeping the state of the battle in a room, from the events
+    /// received from now on. Every battle the bot joins is tracked from its
+    /// `|init|`.
+    pub fn track_battle(&self, room: &str) {
+        self.battles.lock().unwrap()
+            .entry(room.to_string())
+            .or_insert_with(|| BattleState::new(room));
+    }
+
+    /// Updates the state of the battle in a room with an event.
+    pub fn update_battle(&self, room: &str, event: &BattleEvent) {
+        self.battles.lock().unwrap()
+            .entry(room.to_string())
+            .or_insert_with(|| BattleState::new(room))
+            .apply(event);
+
+        // A new turn or the end of one follows the events a request was sent
+        // ahead of
+        match *event {
+            BattleEvent::Turn(_) | BattleEvent::Upkeep =>
+                self.ready_battle(room),
+            _ => (),
+        }
+    }
+
+    /// Forgets the state of the battle in a room.
+    pub fn remove_battle(&self, room: &str) {
+        self.battles.lock().unwrap().remove(room);
+        self.pending.lock().unwrap().remove(room);
+    }
+
+    /// Stores a request for the bot to decide on in a battle. The decision
+    /// is made once the events leading up to the request have arrived.
+    pub fn set_battle_request(&self, room: &str, request: Request) {
+        // Requests for a move come before the events of the turn, while
+        // team preview and forced switches are sent after them
+        if !request.wait {
+            let ready = request.team_preview ||
+                request.force_switch.iter().any(|&f| f);
+            self.pending.lock().unwrap().insert(room.to_string(), ready);
+        }
+        self.battles.lock().unwrap()
+            .entry(room.to_string())
+            .or_insert_with(|| BattleState::new(room))
+            .request = Some(request);
+    }
+
+    /// Marks the request in a room, if any, as ready to be played.
+    fn ready_battle(&self, room: &str) {
+        if let Some(ready) = self.pending.lock().unwrap().get_mut(room) {
+            *ready = true;
+        }
+    }
+
+    /// Asks the `BattleAgent` for a choice if a request in the room is ready
+    /// for one, and sends it. The agent runs without the bot locked.
+    fn play(bot: &Arc<Mutex<Bot>>, room: &str) {
+        let (agent, state) = {
+            let b = bot.lock().unwrap();
+            {
+                let mut pending = b.pending.lock().unwrap();
+                if pending.get(room) != Some(&true) {
+                    return;
+                }
+                pending.remove(room);
+            }
+            match b.battle(room) {
+                Some(state) => (b.agent.clone(), state),
+                None => return,
+            }
+        };
+
+        let request = match state.request {
+            Some(ref request) => request,
+            None => return,
+        };
+        let mut choice = match *agent.lock().unwrap() {
+            Some(ref mut agent) => agent.choose(request, &state),
+            None => return,
+        };
+        if let Err(e) = choice.validate(request) {
+            warn!("[battle] {}: invalid choice {}: {}", room, choice, e);
+            choice = Choice::Default;
+        }
+
+        debug!("[battle] {}: {}", room, choice);
+        bot.lock().unwrap()
+            .send_command(&choice.to_command(request.rqid), room);
+    }
+
+    /// Sets the `ChallengePolicy` that answers incoming challenges. Without
+    /// one, challenges are left unanswered.
+    pub fn set_challenge_policy(&self, policy: Box<ChallengePolicy>) {
+        *self.policy.lock().unwrap() = Some(policy);
+    }
+
+    /// Returns the challenges last sent by the server.
+    pub fn challenges(&self) -> Challenges {
+        self.challenges.lock().unwrap().clone()
+    }
+
+    /// Challenges a user to a battle in a format. The packed team is sent
+    /// first for formats that need one.
+    pub fn challenge(&self, user: &str, format: &str, team: Option<&str>) {
+        if let Some(team) = team {
+            self.send_command(&Command::Utm(team.to_string()), "");
+        }
+        self.send_command(
+            &Command::Challenge(user.to_string(), format.to_string()), "");
+    }
+
+    /// Cancels the challenge sent to a user.
+    pub fn cancel_challenge(&self, user: &str) {
+        self.send_command(&Command::CancelChallenge(user.to_string()), "");
+    }
+
+    /// Accepts a challenge from a user, with a packed team for formats that
+    /// need one.
+    pub fn accept_challenge(&self, user: &str, team: Option<&str>) {
+        if let Some(team) = team {
+            self.send_command(&Command::Utm(team.to_string()), "");
+        }
+        self.send_command(&Command::Accept(user.to_string()), "");
+    }
+
+    pub fn reject_challenge(&self, user: &str) {
+        self.send_command(&Command::Reject(user.to_string()), "");
+    }
+
+    /// Stores the challenges sent by the server and asks the
+    /// `ChallengePolicy` about the new incoming ones. The policy runs
+    /// without the bot locked.
+    pub fn update_challenges(bot: &Arc<Mutex<Bot>>, challenges: Challenges) {
+        let (incoming, policy) = {
+            let b = bot.lock().unwrap();
+            let mut current = b.challenges.lock().unwrap();
+            let incoming: Vec<Challenge> = challenges.challenges_from.iter()
+                .filter(|&(user, format)| {
+                    current.challenges_from.get(user) != Some(format)
+                })
+                .map(|(user, format)| Challenge {
+                    user: user.clone(),
+                    format: format.clone(),
+                    rank: b.user_map.highest_rank_of_user(user),
+                })
+                .collect();
+            *current = challenges;
+            (incoming, b.policy.clone())
+        };
+
+        for challenge in incoming {
+            let decision = match *policy.lock().unwrap() {
+                Some(ref mut policy) => policy.decide(&challenge),
+                None => return,
+            };
+
+            info!("Challenge from {} in {}: {:?}",
+                  challenge.user, challenge.format, decision);
+            let b = bot.lock().unwrap();
+            match decision {
+                ChallengeDecision::Accept(team) =>
+                    b.accept_challenge(&challenge.user,
+                                       team.as_ref().map(|t| &t[..])),
+                ChallengeDecision::Reject =>
+                    b.reject_challenge(&challenge.user),
+                ChallengeDecision::Ignore => (),
+            }
+        }
+    }
+
+    /// Returns the state of the tournament in a room, if one was held there
+    /// since the bot joined.
+    pub fn tournament(&self, room: &str) -> Option<Tournament> {
+        self.tournaments.lock().unwrap().get(room).cloned()
+    }
+
+    /// Adds a `TournamentListener` to tell about the tournament events in
+    /// every room.
+    pub fn subscribe_tournaments(&self, listener: Box<TournamentListener>) {
+        self.listeners.lock().unwrap().push(listener);
+    }
+
+    /// Updates the state of the tournament in a room with an event and tells
+    /// the `TournamentListener`s about it. The listeners run without the bot
+    /// locked.
+    pub fn update_tournament(bot: &Arc<Mutex<Bot>>, room: &str,
+                             event: &TournamentEvent) {
+        let (tournament, listeners) = {
+            let b = bot.lock().unwrap();
+            let mut tournaments = b.tournaments.lock().unwrap();
+            let tournament = tournaments.entry(room.to_string())
+                .or_insert_with(|| Tournament::new(room));
+            tournament.apply(event);
+            (tournament.clone(), b.listeners.clone())
+        };
+
+        match *event {
+            TournamentEvent::Error(ref error) =>
+                warn!("[tournament] {}: {}", room, error),
+            TournamentEvent::End(_) =>
+                info!("[tournament] {}: won by {}", room,
+                      tournament.winner().unwrap_or("nobody")),
+            _ => (),
+        }
+        for listener in listeners.lock().unwrap().iter_mut() {
+            listener.on_event(event, &tournament);
+        }
+    }
+
+    /// Forgets the state of the tournament in a room.
+    pub fn remove_tournament(&self, room: &str) {
+        self.tournaments.lock().unwrap().remove(room);
+    }
+
+    /// Returns the formats the server offers. Empty until the server has
+    /// sent its format list.
+    pub fn formats(&self) -> FormatCatalog {
+        self.formats.lock().unwrap().clone()
+    }
+
+    /// Stores the formats the server offers, warning about ladder formats
+    /// from the config that cannot be searched.
+    pub fn set_formats(&self, formats: FormatCatalog) {
+        for name in &self.config.ladder_formats {
+            match formats.get(name) {
+                Some(format) if format.searchable => (),
+                Some(_) => warn!("[ladder] {} has no ladder", name),
+                None => warn!("[ladder] {} is not a format", name),
+            }
+        }
+        *self.formats.lock().unwrap() = formats;
+    }
+
+    /// Starts searching for ladder battles in the formats from the config.
+    /// Called once the bot is logged in.
+    pub fn start_ladder(&self) {
+        let commands = self.ladder.lock().unwrap().start(&self.config);
+        for command in commands {
+            self.send_command(&command, "");
+        }
+    }
+
+    /// Stores the searches sent by the server and searches for another
+    /// ladder battle if there is room for one.
+    pub fn update_search(&self, search: Search) {
+        let commands = self.ladder.lock().unwrap()
+            .update(search, &self.config);
+        for command in commands {
+            self.send_command(&command, "");
+        }
+    }
+
+    /// Records the result of a finished ladder battle and leaves its room.
+    /// Does nothing unless the bot plays on the ladder.
+    pub fn finish_battle(&mut self, room: &str) {
+        if self.config.ladder_formats.is_empty() {
+            return;
+        }
+        let result = match self.battle(room) {
+            Some(state) => self.ladder.lock().unwrap().finish(&state),
+            None => return,
+        };
+        if let Some(result) = result {
+            let record = self.ladder_record(Some(&result.format));
+            info!("[ladder] {:?} against {} in {} ({}-{}-{})",
+                  result.outcome, result.opponent, result.format,
+                  record.wins, record.losses, record.ties);
+            self.leave_room(room);
+        }
+    }
+
+    /// Returns the results of the ladder battles played so far, oldest
+    /// first.
+    pub fn ladder_results(&self) -> Vec<BattleResult> {
+        self.ladder.lock().unwrap().results().to_vec()
+    }
+
+    /// Returns the tally of the ladder battles played so far in a format, or
+    /// in all formats.
+    pub fn ladder_record(&self, format: Option<&str>) -> Record {
+        self.ladder.lock().unwrap().record(format)
+    }
+
+    /// Asks the server a `Query` and waits up to `timeout` for the answer,
+    /// read as a `T` such as a `serde_json::Value`. Answers are received by
+    /// the thread that runs plugins, so this returns an error when called
+    /// from a plugin; use `query_with` there. Do not hold the lock on the
+    /// bot while waiting, as the answer may be stuck behind messages that
+    /// need it.
+    pub fn query<T: DeserializeOwned>(&self, query: &Query, timeout: Duration)
+        -> ::Result<T>
+    {
+        if *self.receiver.lock().unwrap() == Some(thread::current().id()) {
+            return Err(::Error::QueryOnReceiveThread);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let id = self.queries.lock().unwrap()
+            .register(query, Responder::Channel(tx));
+        self.send_command(&query.to_command(), "");
+
+        match rx.recv_timeout(timeout) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(RecvTimeoutError::Timeout) => {
+                self.queries.lock().unwrap().cancel(id);
+                Err(::Error::QueryTimeout(query.query_type().to_string()))
+            },
+            Err(RecvTimeoutError::Disconnected) =>
+                Err(::Error::ChanRecv(RecvError)),
+        }
+    }
+
+    /// Asks the server a `Query` and calls `callback` with the answer, read
+    /// as a `T`, on the thread that runs plugins. The callback is dropped
+    /// without being called if the connection is lost first.
+    pub fn query_with<T, F>(&self, query: &Query, callback: F)
+        where T: DeserializeOwned,
+              F: FnOnce(::Result<T>) + Send + 'static,
+    {
+        let mut callback = Some(callback);
+        let responder = Responder::Callback(Box::new(move |json: &str| {
+            if let Some(callback) = callback.take() {
+                callback(serde_json::from_str(json).map_err(::Error::from));
+            }
+        }));
+        self.queries.lock().unwrap().register(query, responder);
+        self.send_command(&query.to_command(), "");
+    }
+
+    /// Looks a user up with a `userdetails` query and updates `user_map`
+    /// with the answer. Answers are reused for `user_details_ttl_ms`. Blocks
+    /// like `query`, so plugins use `user_details_with` instead.
+    pub fn user_details(&self, name: &str) -> ::Result<UserDetails> {
+        if let Some(details) = self.cached_user_details(name) {
+            return Ok(details);
+        }
+
+        let timeout = Duration::from_millis(self.config.query_timeout_ms);
+        let details: UserDetails =
+            self.query(&Query::UserDetails(name.to_string()), timeout)?;
+        Bot::cache_user_details(&self.details, &self.user_map, &details);
+        Ok(details)
+    }
+
+    /// Looks a user up like `user_details` and calls `callback` with the
+    /// answer, right away if it is cached.
+    pub fn user_details_with<F>(&self, name: &str, callback: F)
+        where F: FnOnce(::Result<UserDetails>) + Send + 'static,
+    {
+        if let Some(details) = self.cached_user_details(name) {
+            return callback(Ok(details));
+        }
+
+        let cache = self.details.clone();
+        let users = self.user_map.clone();
+        self.query_with(&Query::UserDetails(name.to_string()),
+                        move |details: ::Result<UserDetails>| {
+                            if let Ok(ref details) = details {
+                                Bot::cache_user_details(&cache, &users,
+                                                        details);
+                            }
+                            callback(details);
+                        });
+    }
+
+    /// Returns the details of a user looked up less than
+    /// `user_details_ttl_ms` ago.
+    pub fn cached_user_details(&self, name: &str) -> Option<UserDetails> {
+        let ttl = Duration::from_millis(self.config.user_details_ttl_ms);
+        match self.details.lock().unwrap().get(&sanitize(name)) {
+            Some(&(at, ref details)) if at.elapsed() < ttl =>
+                Some(details.clone()),
+            _ => None,
+        }
+    }
+
+    fn cache_user_details(cache: &Mutex<DetailsCache>, users: &CacheMap<User>,
+                          details: &UserDetails) {
+        cache.lock().unwrap()
+            .insert(details.id.clone(), (Instant::now(), details.clone()));
+        users.update_details(details);
+    }
+
+    /// Hands an answer to the query waiting for it.
+    fn answer(queries: &Mutex<Queries>, query_type: &str, json: &str) {
+        let responder = queries.lock().unwrap().resolve(query_type, json);
+        match responder {
+            Some(Responder::Channel(tx)) => {
+                let _ = tx.send(json.to_string());
+            },
+            Some(Responder::Callback(mut callback)) => callback(json),
+            None => debug!("No query waiting for {} answer", query_type),
+        }
+    }
+
+    /// Marks the bot as logged in, so that losing the connection from now on
+    /// starts the reconnect attempts over.
+    pub fn set_logged_in(&self) {
+        self.logged_in.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether the bot has logged in on this connection.
+    pub fn is_logged_in(&self) -> bool {
+        self.logged_in.load(Ordering::SeqCst)
     }
 
     /// Set the login time.
@@ -268,14 +829,11 @@ impl Bot {
         self.login_time = timestamp;
     }
 
+    /// Logs in with the credentials from the config and sends the resulting
+    /// assertion to the server.
     pub fn login(&self, challstr: &str) -> ::Result<()> {
-        let (user, pass) = {
-            let u = env::var("BOT_USERNAME").unwrap();
-            let p = env::var("BOT_PASSWORD").unwrap();
-
-            (u, p)
-        };
-        let client = ::reqwest::Client::new().unwrap();
+        let (user, pass) = self.config.credentials()?;
+        let client = ::reqwest::Client::new()?;
         let sanitized_user = &::helpers::sanitize(&user);
 
         let mut params = HashMap::new();
@@ -290,18 +848,91 @@ impl Bot {
         params.insert("challstr", challstr);
 
         let mut res = client
-            .post("https://play.pokemonshowdown.com/action.php")
+            .post(&self.config.login_server)
             .form(&params)
             .send()?;
 
         let mut buf = String::new();
         res.read_to_string(&mut buf)?;
-        let data_str = &buf[1..];
+        let assertion = parse_assertion(&buf, pass.is_empty())?;
 
-        let v: Value = ::serde_json::from_str(&data_str)?;
-        let assertion = v["assertion"].as_str().unwrap();
-
-        self.send(format!("|/trn {},0,{}", user, assertion));
+        self.send_command(&Command::Trn(user, assertion), "");
         Ok(())
     }
 }
+
+/// Extracts the assertion from a login server response. A `getassertion`
+/// request is answered with the bare assertion, a `login` request with
+/// `]` followed by JSON. Either way, an assertion starting with `;;` is an
+/// error message from the login server, and a bare `;` means the name is
+/// registered and cannot be used without its password.
+fn parse_assertion(body: &str, bare: bool) -> ::Result<String> {
+    let body = body.trim();
+    let assertion = if bare {
+        body.to_string()
+    } else {
+        let json = if body.starts_with("]") { &body[1..] } else { body };
+        let v: Value = ::serde_json::from_str(json)?;
+        match v["assertion"].as_str() {
+            Some(a) => a.to_string(),
+            None => return Err(::Error::MissingAssertion),
+        }
+    };
+
+    if assertion.starts_with(";;") {
+        Err(::Error::LoginRejected(assertion[2..].to_string()))
+    } else if assertion == ";" {
+        Err(::Error::PasswordRequired)
+    } else if assertion.is_empty() {
+        Err(::Error::MissingAssertion)
+    } else {
+        Ok(assertion)
+    }
+}
+
+/// Computes how long to wait before the given reconnect attempt. The delay
+/// doubles with every attempt up to `reconnect_max_ms`, and half of it is
+/// randomized so that many bots do not reconnect in lockstep.
+fn backoff_delay(config: &::Config, attempt: u32) -> u64 {
+    let exp = ::std::cmp::min(attempt.saturating_sub(1), 16);
+    let delay = ::std::cmp::min(
+        config.reconnect_base_ms.saturating_mul(1 << exp),
+        config.reconnect_max_ms);
+    let half = delay / 2;
+    if half == 0 {
+        return delay;
+    }
+    half + rand::thread_rng().gen_range(0, half + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_assertion;
+    use ::Error;
+
+    #[test]
+    fn parse_assertion_test() {
+        assert_eq!(parse_assertion("abc123", true).unwrap(), "abc123");
+        assert_eq!(parse_assertion(
+            "]{\"actionsuccess\":true,\"assertion\":\"abc123\"}", false)
+            .unwrap(), "abc123");
+
+        match parse_assertion(";;Your username is already taken.", true) {
+            Err(Error::LoginRejected(ref m)) =>
+                assert_eq!(m, "Your username is already taken."),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match parse_assertion(";\n", true) {
+            Err(Error::PasswordRequired) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match parse_assertion("]{\"actionsuccess\":false}", false) {
+            Err(Error::MissingAssertion) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match parse_assertion("]<html>", false) {
+            Err(Error::Json(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
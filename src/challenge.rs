@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use serde_json;
+
+use helpers::sanitize;
+use rank::Rank;
+
+/// The challenges of the bot, sent as `|updatechallenges|JSON` whenever they
+/// change.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Challenges {
+    /// Incoming challenges, from user id to format.
+    #[serde(default)]
+    pub challenges_from: BTreeMap<String, String>,
+    /// The challenge the bot sent, if any.
+    #[serde(default)]
+    pub challenge_to: Option<OutgoingChallenge>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct OutgoingChallenge {
+    pub to: String,
+    pub format: String,
+}
+
+impl Challenges {
+    pub fn parse(json: &str) -> ::Result<Challenges> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// An incoming challenge.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Challenge {
+    /// The id of the user who sent it.
+    pub user: String,
+    pub format: String,
+    /// The highest rank the user is known to have in any room.
+    pub rank: Rank,
+}
+
+/// What to do with an incoming challenge.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChallengeDecision {
+    /// Accept with a packed team, or with no team for formats that do not
+    /// need one.
+    Accept(Option<String>),
+    Reject,
+    /// Leave the challenge for someone to answer by hand.
+    Ignore,
+}
+
+/// Decides which incoming challenges the bot accepts. Each challenge is
+/// decided once, when it first shows up.
+pub trait ChallengePolicy: Send + Debug {
+    fn decide(&mut self, challenge: &Challenge) -> ChallengeDecision;
+}
+
+/// A `ChallengePolicy` accepting challenges that match all of its filters
+/// and rejecting the rest. Empty filters match everything.
+#[derive(Clone, Debug, Default)]
+pub struct ChallengeFilter {
+    /// User ids allowed to challenge the bot.
+    pub users: Vec<String>,
+    /// Formats the bot plays, with the packed team to use for each, if any.
+    pub formats: BTreeMap<String, Option<String>>,
+    /// The lowest rank allowed to challenge the bot.
+    pub min_rank: Option<Rank>,
+}
+
+impl ChallengePolicy for ChallengeFilter {
+    fn decide(&mut self, challenge: &Challenge) -> ChallengeDecision {
+        let user = self.users.is_empty() ||
+            self.users.iter().any(|u| sanitize(u) == challenge.user);
+        let rank = self.min_rank.map_or(true, |r| challenge.rank.at_least(r));
+        let team = if self.formats.is_empty() {
+            Some(None)
+        } else {
+            self.formats.get(&challenge.format).cloned()
+        };
+
+        match team {
+            Some(team) if user && rank => ChallengeDecision::Accept(team),
+            _ => ChallengeDecision::Reject,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Challenge, ChallengeDecision, ChallengeFilter, ChallengePolicy,
+                Challenges};
+    use rank::Rank;
+
+    #[test]
+    fn parse_test() {
+        let challenges = Challenges::parse(r#"{
+            "challengesFrom": {"alice": "gen9ou"},
+            "challengeTo": {"to": "bob", "format": "gen9randombattle"}
+        }"#).unwrap();
+        assert_eq!(challenges.challenges_from.get("alice"),
+                   Some(&"gen9ou".to_string()));
+        assert_eq!(challenges.challenge_to.unwrap().to, "bob");
+
+        let challenges = Challenges::parse(
+            r#"{"challengesFrom": {}, "challengeTo": null}"#).unwrap();
+        assert!(challenges.challenges_from.is_empty());
+        assert!(challenges.challenge_to.is_none());
+    }
+
+    #[test]
+    fn filter_test() {
+        let mut filter = ChallengeFilter::default();
+        filter.users = vec!["Alice".to_string()];
+        filter.formats.insert("gen9randombattle".to_string(), None);
+        filter.min_rank = Some(Rank::Voice);
+
+        let mut challenge = Challenge {
+            user: "alice".to_string(),
+            format: "gen9randombattle".to_string(),
+            rank: Rank::Voice,
+        };
+        assert_eq!(filter.decide(&challenge), ChallengeDecision::Accept(None));
+
+        challenge.format = "gen9ou".to_string();
+        assert_eq!(filter.decide(&challenge), ChallengeDecision::Reject);
+
+        challenge.format = "gen9randombattle".to_string();
+        challenge.rank = Rank::Regular;
+        assert_eq!(filter.decide(&challenge), ChallengeDecision::Reject);
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+
+use outbox::Priority;
+
+/// A chat command the bot can send, so that plugins do not have to build
+/// protocol strings by hand. `Display` renders the command as typed in chat,
+/// e.g. `/mute user, reason`, and `to_line` renders the full protocol line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// `/join ROOM`
+    Join(String),
+    /// `/leave ROOM`
+    Leave(String),
+    /// `/w USER,MESSAGE`
+    Whisper(String, String),
+    /// `/avatar AVATAR`
+    Avatar(u64),
+    /// `/trn NAME,0,ASSERTION`, or `/trn NAME` if the assertion is empty.
+    Trn(String, String),
+    /// `/status MESSAGE`
+    Status(String),
+    /// `/away [MESSAGE]`
+    Away(String),
+    /// `/back`
+    Back,
+    /// `/htmlbox HTML`
+    HtmlBox(String),
+    /// `/addhtmlbox HTML`
+    AddHtmlBox(String),
+    /// `/adduhtml NAME, HTML`
+    AddUhtml(String, String),
+    /// `/changeuhtml NAME, HTML`
+    ChangeUhtml(String, String),
+    /// `/mute USER[, REASON]`
+    Mute(String, String),
+    /// `/hourmute USER[, REASON]`
+    HourMute(String, String),
+    /// `/unmute USER`
+    Unmute(String),
+    /// `/warn USER[, REASON]`
+    Warn(String, String),
+    /// `/roomban USER[, REASON]`
+    RoomBan(String, String),
+    /// `/roomunban USER`
+    RoomUnban(String),
+    /// `/modnote NOTE`
+    ModNote(String),
+    /// `/declare MESSAGE`
+    Declare(String),
+    /// `/choose CHOICE|RQID`, answering a battle request.
+    Choose(String, Option<u64>),
+    /// `/team ORDER|RQID`, answering a team preview request.
+    Team(String, Option<u64>),
+    /// `/utm TEAM`, with a packed team, or `/utm null` if it is empty.
+    Utm(String),
+    /// `/challenge USER, FORMAT`
+    Challenge(String, String),
+    /// `/cancelchallenge USER`
+    CancelChallenge(String),
+    /// `/accept USER`
+    Accept(String),
+    /// `/reject USER`
+    Reject(String),
+    /// `/search FORMAT`, searching for a ladder battle.
+    Search(String),
+    /// `/cancelsearch`
+    CancelSearch,
+    /// `/cmd QUERYTYPE ARGS`, answered with `|queryresponse|`.
+    Query(String, String),
+}
+
+impl Command {
+    /// Returns true if the command acts on the room it is sent from, and
+    /// false if it is global.
+    pub fn is_room_command(&self) -> bool {
+        match *self {
+            Command::Join(_) |
+            Command::Leave(_) |
+            Command::Whisper(..) |
+            Command::Avatar(_) |
+            Command::Trn(..) |
+            Command::Status(_) |
+            Command::Away(_) |
+            Command::Back |
+            Command::Utm(_) |
+            Command::Challenge(..) |
+            Command::CancelChallenge(_) |
+            Command::Accept(_) |
+            Command::Reject(_) |
+            Command::Search(_) |
+            Command::CancelSearch |
+            Command::Query(..) => false,
+            _ => true,
+        }
+    }
+
+    /// Returns the `Priority` the command should be sent with. Moderation
+    /// commands skip ahead of chat.
+    pub fn priority(&self) -> Priority {
+        match *self {
+            Command::Mute(..) |
+            Command::HourMute(..) |
+            Command::Unmute(_) |
+            Command::Warn(..) |
+            Command::RoomBan(..) |
+            Command::RoomUnban(_) |
+            Command::ModNote(_) => Priority::Moderation,
+            _ => Priority::Reply,
+        }
+    }
+
+    /// Renders the protocol line for sending the command from `room`. Global
+    /// commands ignore the room.
+    pub fn to_line(&self, room: &str) -> String {
+        if self.is_room_command() {
+            format!("{}|{}", room, self)
+        } else {
+            format!("|{}", self)
+        }
+    }
+}
+
+/// Writes `/command ARGS` followed by `|RQID` if there is a request id.
+fn with_rqid(f: &mut fmt::Formatter, command: &str, args: &str,
+             rqid: Option<u64>) -> fmt::Result {
+    match rqid {
+        Some(rqid) => write!(f, "/{} {}|{}", command, args, rqid),
+        None => write!(f, "/{} {}", command, args),
+    }
+}
+
+/// Writes `/command TARGET` followed by `, REASON` if there is a reason.
+fn with_reason(f: &mut fmt::Formatter, command: &str, target: &str,
+               reason: &str) -> fmt::Result {
+    if reason.is_empty() {
+        write!(f, "/{} {}", command, target)
+    } else {
+        write!(f, "/{} {}, {}", command, target, reason)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::Join(ref room) => write!(f, "/join {}", room),
+            Command::Leave(ref room) => write!(f, "/leave {}", room),
+            Command::Whisper(ref user, ref message) =>
+                write!(f, "/w {},{}", user, message),
+            Command::Avatar(avatar) => write!(f, "/avatar {}", avatar),
+            Command::Trn(ref name, ref assertion) => if assertion.is_empty() {
+                write!(f, "/trn {}", name)
+            } else {
+                write!(f, "/trn {},0,{}", name, assertion)
+            },
+            Command::Status(ref message) => write!(f, "/status {}", message),
+            Command::Away(ref message) => if message.is_empty() {
+                write!(f, "/away")
+            } else {
+                write!(f, "/away {}", message)
+            },
+            Command::Back => write!(f, "/back"),
+            Command::HtmlBox(ref html) => write!(f, "/htmlbox {}", html),
+            Command::AddHtmlBox(ref html) => write!(f, "/addhtmlbox {}", html),
+            Command::AddUhtml(ref name, ref html) =>
+                write!(f, "/adduhtml {}, {}", name, html),
+            Command::ChangeUhtml(ref name, ref html) =>
+                write!(f, "/changeuhtml {}, {}", name, html),
+            Command::Mute(ref user, ref reason) =>
+                with_reason(f, "mute", user, reason),
+            Command::HourMute(ref user, ref reason) =>
+                with_reason(f, "hourmute", user, reason),
+            Command::Unmute(ref user) => write!(f, "/unmute {}", user),
+            Command::Warn(ref user, ref reason) =>
+                with_reason(f, "warn", user, reason),
+            Command::RoomBan(ref user, ref reason) =>
+                with_reason(f, "roomban", user, reason),
+            Command::RoomUnban(ref user) => write!(f, "/roomunban {}", user),
+            Command::ModNote(ref note) => write!(f, "/modnote {}", note),
+            Command::Declare(ref message) => write!(f, "/declare {}", message),
+            Command::Choose(ref choice, rqid) =>
+                with_rqid(f, "choose", choice, rqid),
+            Command::Team(ref order, rqid) => with_rqid(f, "team", order, rqid),
+            Command::Utm(ref team) => if team.is_empty() {
+                write!(f, "/utm null")
+            } else {
+                write!(f, "/utm {}", team)
+            },
+            Command::Challenge(ref user, ref format) =>
+                write!(f, "/challenge {}, {}", user, format),
+            Command::CancelChallenge(ref user) =>
+                write!(f, "/cancelchallenge {}", user),
+            Command::Accept(ref user) => write!(f, "/accept {}", user),
+            Command::Reject(ref user) => write!(f, "/reject {}", user),
+            Command::Search(ref format) => write!(f, "/search {}", format),
+            Command::CancelSearch => write!(f, "/cancelsearch"),
+            Command::Query(ref query_type, ref args) => if args.is_empty() {
+                write!(f, "/cmd {}", query_type)
+            } else {
+                write!(f, "/cmd {} {}", query_type, args)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+
+    #[test]
+    fn to_line_test() {
+        assert_eq!(Command::Join("techcode".to_string()).to_line("lobby"),
+                   "|/join techcode");
+        assert_eq!(Command::Whisper("user".to_string(), "hi".to_string())
+                   .to_line(""), "|/w user,hi");
+        assert_eq!(Command::Trn("bot".to_string(), "abc".to_string())
+                   .to_line(""), "|/trn bot,0,abc");
+        assert_eq!(Command::Mute("user".to_string(), String::new())
+                   .to_line("techcode"), "techcode|/mute user");
+        assert_eq!(Command::Warn("user".to_string(), "spam".to_string())
+                   .to_line("techcode"), "techcode|/warn user, spam");
+        assert_eq!(Command::Challenge("user".to_string(), "gen9ou".to_string())
+                   .to_line("lobby"), "|/challenge user, gen9ou");
+        assert_eq!(Command::Utm(String::new()).to_line(""), "|/utm null");
+        assert_eq!(Command::Search("gen9randombattle".to_string())
+                   .to_line("lobby"), "|/search gen9randombattle");
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+use std::env;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::prelude::*;
@@ -11,16 +13,66 @@ pub struct Config {
     pub host: String,
     #[serde(default="default_port")]
     pub port: String,
-    #[serde(default="default_mps")]
+    #[serde(default="default_scheme")]
+    pub scheme: String,
+    #[serde(default="default_path")]
+    pub path: String,
+    #[serde(default="Default::default")]
+    pub ca_file: Option<String>,
+    #[serde(default="default_throttle_ms")]
     pub throttle_ms: u64,
+    #[serde(default="default_trusted_throttle_ms")]
+    pub trusted_throttle_ms: u64,
+    #[serde(default="default_throttle_burst")]
+    pub throttle_burst: u32,
+    #[serde(default="Default::default")]
+    pub trusted: bool,
     #[serde(default="Default::default")]
     pub rooms: Vec<String>,
     #[serde(default="Default::default")]
     pub avatar: u64,
+    #[serde(default="default_max_message_length")]
+    pub max_message_length: usize,
+    #[serde(default="Default::default")]
+    pub max_message_parts: usize,
+    #[serde(default="Default::default")]
+    pub number_message_parts: bool,
     #[serde(default="Default::default")]
     pub plugin_prefixes: Vec<String>,
     #[serde(default="Default::default")]
     pub case_insensitive: bool,
+    #[serde(default="default_login_server")]
+    pub login_server: String,
+    #[serde(default="Default::default")]
+    pub username: Option<String>,
+    #[serde(default="Default::default")]
+    pub password: Option<String>,
+    #[serde(default="Default::default")]
+    pub password_file: Option<String>,
+    #[serde(default="Default::default")]
+    pub password_env: Option<String>,
+    #[serde(default="Default::default")]
+    pub guest: bool,
+    #[serde(default="Default::default")]
+    pub guest_name: Option<String>,
+    #[serde(default="default_reconnect")]
+    pub reconnect: bool,
+    #[serde(default="default_reconnect_base_ms")]
+    pub reconnect_base_ms: u64,
+    #[serde(default="default_reconnect_max_ms")]
+    pub reconnect_max_ms: u64,
+    #[serde(default="Default::default")]
+    pub reconnect_max_attempts: u32,
+    #[serde(default="Default::default")]
+    pub ladder_formats: Vec<String>,
+    #[serde(default="default_ladder_max_battles")]
+    pub ladder_max_battles: usize,
+    #[serde(default="Default::default")]
+    pub ladder_teams: BTreeMap<String, String>,
+    #[serde(default="default_query_timeout_ms")]
+    pub query_timeout_ms: u64,
+    #[serde(default="default_user_details_ttl_ms")]
+    pub user_details_ttl_ms: u64,
 }
 
 impl Config {
@@ -32,11 +84,59 @@ impl Config {
         let mut contents = String::new();
         br.read_to_string(&mut contents)?;
 
-        let decoded: Config = toml::from_str(&contents)?;
+        let mut decoded: Config = toml::from_str(&contents)?;
+        decoded.apply_env();
 
         Ok(decoded)
     }
 
+    /// Overrides the login settings with the `BOT_USERNAME`, `BOT_PASSWORD`
+    /// and `BOT_LOGIN_SERVER` environment variables if they are set.
+    /// `BOT_PASSWORD` takes the place of every password setting, including
+    /// `password_file` and `password_env`.
+    pub fn apply_env(&mut self) {
+        self.apply_vars(|name| env::var(name).ok());
+    }
+
+    fn apply_vars<F: Fn(&str) -> Option<String>>(&mut self, var: F) {
+        if let Some(u) = var("BOT_USERNAME") {
+            self.username = Some(u);
+        }
+        if let Some(p) = var("BOT_PASSWORD") {
+            self.password = Some(p);
+            self.password_file = None;
+            self.password_env = None;
+        }
+        if let Some(s) = var("BOT_LOGIN_SERVER") {
+            self.login_server = s;
+        }
+    }
+
+    /// Returns the username and password to log in with. The password is
+    /// looked up in `password_env`, then `password_file`, then `password`,
+    /// and is empty if none are set. Returns an error if there is no
+    /// username or the password file cannot be read.
+    pub fn credentials(&self) -> ::Result<(String, String)> {
+        let user = match self.username {
+            Some(ref u) if !u.is_empty() => u.clone(),
+            _ => return Err(::Error::MissingCredentials),
+        };
+
+        if let Some(ref var) = self.password_env {
+            if let Ok(p) = env::var(var) {
+                return Ok((user, p));
+            }
+        }
+        if let Some(ref path) = self.password_file {
+            let mut pass = String::new();
+            File::open(path)?.read_to_string(&mut pass)?;
+            return Ok((user, pass.trim().to_string()));
+        }
+        let pass = self.password.clone().unwrap_or_default();
+
+        Ok((user, pass))
+    }
+
     pub fn prefix_string(&self) -> String {
         "^(".to_string() + &self.plugin_prefixes.join("|") + ")"
     }
@@ -44,4 +144,39 @@ impl Config {
 
 fn default_host() -> String { "sim.smogon.com".to_string() }
 fn default_port() -> String { "8000".to_string() }
-fn default_mps() -> u64 { 333 }
+fn default_scheme() -> String { "ws".to_string() }
+fn default_path() -> String { "/showdown/websocket".to_string() }
+fn default_throttle_ms() -> u64 { 600 }
+fn default_trusted_throttle_ms() -> u64 { 100 }
+fn default_throttle_burst() -> u32 { 5 }
+fn default_max_message_length() -> usize { 300 }
+fn default_login_server() -> String {
+    "https://play.pokemonshowdown.com/action.php".to_string()
+}
+fn default_reconnect() -> bool { true }
+fn default_reconnect_base_ms() -> u64 { 1000 }
+fn default_reconnect_max_ms() -> u64 { 300000 }
+fn default_ladder_max_battles() -> usize { 1 }
+fn default_query_timeout_ms() -> u64 { 5000 }
+fn default_user_details_ttl_ms() -> u64 { 60000 }
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn password_precedence_test() {
+        let mut config = Config::new("examples/example_config.toml").unwrap();
+        config.username = Some("Bot".to_string());
+        config.password_file = Some("missing/password.txt".to_string());
+        config.password_env = Some("SHOWDOWN_TEST_UNSET_PASSWORD".to_string());
+        assert!(config.credentials().is_err());
+
+        config.apply_vars(|name| match name {
+            "BOT_PASSWORD" => Some("hunter2".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.credentials().unwrap(),
+                   ("Bot".to_string(), "hunter2".to_string()));
+    }
+}
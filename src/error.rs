@@ -4,11 +4,20 @@ use std::fmt;
 #[derive(Debug)]
 pub enum Error {
     ChanRecv(::std::sync::mpsc::RecvError),
-    ChanSend(::std::sync::mpsc::SendError<::websocket::Message<'static>>),
     Http(::reqwest::Error),
+    InvalidTeam(String),
     Io(::std::io::Error),
     Json(::serde_json::Error),
+    LoginRejected(String),
+    MissingAssertion,
+    MissingCredentials,
+    Parse(::protocol::ParseError),
+    PasswordRequired,
+    QueryOnReceiveThread,
+    QueryTimeout(String),
+    ReconnectLimit(u32),
     Socket(::websocket::result::WebSocketError),
+    Tls(::native_tls::Error),
     Toml(::toml::de::Error),
     Url(::websocket::url::ParseError)
 }
@@ -17,11 +26,25 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::ChanRecv(ref e) => fmt::Display::fmt(e, f),
-            Error::ChanSend(ref e) => fmt::Display::fmt(e, f),
             Error::Http(ref e) => fmt::Display::fmt(e, f),
+            Error::InvalidTeam(ref s) => write!(f, "invalid team: {}", s),
             Error::Io(ref e) => fmt::Display::fmt(e, f),
             Error::Json(ref e) => fmt::Display::fmt(e, f),
+            Error::LoginRejected(ref m) => write!(f, "login rejected: {}", m),
+            Error::MissingAssertion =>
+                write!(f, "login server response has no assertion"),
+            Error::MissingCredentials =>
+                write!(f, "no username configured to log in with"),
+            Error::Parse(ref e) => fmt::Display::fmt(e, f),
+            Error::PasswordRequired =>
+                write!(f, "the username is registered and needs a password"),
+            Error::QueryOnReceiveThread =>
+                write!(f, "cannot wait for a query on the receive thread"),
+            Error::QueryTimeout(ref t) => write!(f, "no answer to {} query", t),
+            Error::ReconnectLimit(n) =>
+                write!(f, "gave up after {} reconnect attempts", n),
             Error::Socket(ref e) => fmt::Display::fmt(e, f),
+            Error::Tls(ref e) => fmt::Display::fmt(e, f),
             Error::Toml(ref e) => fmt::Display::fmt(e, f),
             Error::Url(ref e) => fmt::Display::fmt(e, f)
         }
@@ -32,11 +55,22 @@ impl StdError for Error {
     fn description(&self) -> &str {
         match *self {
             Error::ChanRecv(ref e) => e.description(),
-            Error::ChanSend(ref e) => e.description(),
             Error::Http(ref e) => e.description(),
+            Error::InvalidTeam(_) => "invalid team",
             Error::Io(ref e) => e.description(),
             Error::Json(ref e) => e.description(),
+            Error::LoginRejected(_) => "login rejected",
+            Error::MissingAssertion => "login server response has no assertion",
+            Error::MissingCredentials => "no username configured to log in with",
+            Error::Parse(ref e) => e.description(),
+            Error::PasswordRequired =>
+                "the username is registered and needs a password",
+            Error::QueryOnReceiveThread =>
+                "cannot wait for a query on the receive thread",
+            Error::QueryTimeout(_) => "no answer to query",
+            Error::ReconnectLimit(_) => "reconnect attempt limit reached",
             Error::Socket(ref e) => e.description(),
+            Error::Tls(ref e) => e.description(),
             Error::Toml(ref e) => e.description(),
             Error::Url(ref e) => e.description()
         }
@@ -45,11 +79,20 @@ impl StdError for Error {
     fn cause(&self) -> Option<&StdError> {
         match *self {
             Error::ChanRecv(ref e) => Some(e),
-            Error::ChanSend(ref e) => Some(e),
             Error::Http(ref e) => Some(e),
+            Error::InvalidTeam(_) => None,
             Error::Io(ref e) => Some(e),
             Error::Json(ref e) => Some(e),
+            Error::LoginRejected(_) => None,
+            Error::MissingAssertion => None,
+            Error::MissingCredentials => None,
+            Error::Parse(ref e) => Some(e),
+            Error::PasswordRequired => None,
+            Error::QueryOnReceiveThread => None,
+            Error::QueryTimeout(_) => None,
+            Error::ReconnectLimit(_) => None,
             Error::Socket(ref e) => Some(e),
+            Error::Tls(ref e) => Some(e),
             Error::Toml(ref e) => Some(e),
             Error::Url(ref e) => Some(e)
         }
@@ -62,12 +105,6 @@ impl From<::std::sync::mpsc::RecvError> for Error {
     }
 }
 
-impl From<::std::sync::mpsc::SendError<::websocket::Message<'static>>> for Error {
-    fn from (err: ::std::sync::mpsc::SendError<::websocket::Message<'static>>) -> Error {
-        Error::ChanSend(err)
-    }
-}
-
 impl From<::reqwest::Error> for Error {
     fn from (err: ::reqwest::Error) -> Error {
         Error::Http(err)
@@ -86,6 +123,12 @@ impl From<::serde_json::Error> for Error {
     }
 }
 
+impl From<::protocol::ParseError> for Error {
+    fn from(err: ::protocol::ParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
 impl From<::websocket::url::ParseError> for Error {
     fn from(err: ::websocket::url::ParseError) -> Error {
         Error::Url(err)
@@ -104,4 +147,10 @@ impl From<::websocket::result::WebSocketError> for Error {
     }
 }
 
+impl From<::native_tls::Error> for Error {
+    fn from(err: ::native_tls::Error) -> Error {
+        Error::Tls(err)
+    }
+}
+
 pub type Result<T> = ::std::result::Result<T, Error>;
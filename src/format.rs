@@ -0,0 +1,161 @@
+use helpers::sanitize;
+
+/// A format the server offers, like `[Gen 9] OU`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Format {
+    pub name: String,
+    /// The id used in commands, e.g. `gen9ou`.
+    pub id: String,
+    /// The name of the section the format is listed in.
+    pub section: String,
+    /// True if players bring their own team, false if the server hands out
+    /// random teams.
+    pub team_required: bool,
+    /// True if the format has a ladder that can be searched.
+    pub searchable: bool,
+    pub challengeable: bool,
+    pub tournament: bool,
+    /// True if teams are built at level 50.
+    pub level_50: bool,
+}
+
+impl Format {
+    /// Parses a format entry, with its flags as a hex number after the last
+    /// comma, e.g. `[Gen 9] OU,e`. Servers that predate the flags mark
+    /// formats with `,#`, `,,` or `,` suffixes instead.
+    fn parse(entry: &str, section: &str) -> Format {
+        let flags = entry.rfind(',')
+            .and_then(|i| u32::from_str_radix(&entry[i + 1..], 16).ok()
+                      .map(|flags| (i, flags)));
+        let (name, flags) = match flags {
+            Some((i, flags)) => (&entry[..i], flags),
+            None if entry.ends_with(",#") => (&entry[..entry.len() - 2], 0xf),
+            None if entry.ends_with(",,") => (&entry[..entry.len() - 2], 0xa),
+            None if entry.ends_with(',') => (&entry[..entry.len() - 1], 0xc),
+            None => (entry, 0xe),
+        };
+
+        Format {
+            name: name.to_string(),
+            id: sanitize(name),
+            section: section.to_string(),
+            team_required: flags & 0x1 == 0,
+            searchable: flags & 0x2 != 0,
+            challengeable: flags & 0x4 != 0,
+            tournament: flags & 0x8 != 0,
+            level_50: flags & 0x10 != 0,
+        }
+    }
+}
+
+/// A section of the format list, like `S/V Singles`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Section {
+    pub name: String,
+    /// The column the client shows the section in.
+    pub column: u32,
+    pub formats: Vec<Format>,
+}
+
+/// The formats the server offers, sent as `|formats|FORMATSLIST` when the
+/// bot connects.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FormatCatalog {
+    pub sections: Vec<Section>,
+}
+
+impl FormatCatalog {
+    /// Parses the format list, e.g.
+    /// `,1|S/V Singles|[Gen 9] Random Battle,f|[Gen 9] OU,e`. A section
+    /// starts with its column after a comma, or an empty entry to keep the
+    /// column, followed by its name.
+    pub fn parse(list: &str) -> FormatCatalog {
+        let mut sections: Vec<Section> = Vec::new();
+        let mut column = 0;
+        let mut header = false;
+
+        for entry in list.split('|') {
+            if header {
+                sections.push(Section {
+                    name: entry.to_string(),
+                    column,
+                    formats: Vec::new(),
+                });
+                header = false;
+            } else if entry.is_empty() {
+                header = true;
+            } else if entry.starts_with(',') {
+                // Entries like ,LL flag the whole list, not a section
+                if let Ok(c) = entry[1..].parse() {
+                    column = c;
+                    header = true;
+                }
+            } else {
+                if sections.is_empty() {
+                    sections.push(Section::default());
+                }
+                let section = sections.last_mut().unwrap();
+                let format = Format::parse(entry, &section.name);
+                section.formats.push(format);
+            }
+        }
+
+        FormatCatalog { sections }
+    }
+
+    /// Returns every format, in the order of the list.
+    pub fn formats(&self) -> Vec<&Format> {
+        self.sections.iter().flat_map(|s| s.formats.iter()).collect()
+    }
+
+    /// Looks a format up by its name or id.
+    pub fn get(&self, name: &str) -> Option<&Format> {
+        let id = sanitize(name);
+        self.sections.iter()
+            .flat_map(|s| s.formats.iter())
+            .find(|f| f.id == id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.iter().all(|s| s.formats.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FormatCatalog;
+
+    #[test]
+    fn parse_test() {
+        let catalog = FormatCatalog::parse(concat!(
+            ",1|S/V Singles|[Gen 9] Random Battle,f|[Gen 9] OU,e|",
+            "[Gen 9] Custom Game,c|,LL|,2|Past Gens OU|[Gen 3] OU,e|",
+            "|Past Gens Doubles|[Gen 8] VGC 2022,1e"));
+
+        assert_eq!(catalog.sections.len(), 3);
+        assert_eq!(catalog.sections[0].name, "S/V Singles");
+        assert_eq!(catalog.sections[2].column, 2);
+        assert_eq!(catalog.formats().len(), 5);
+
+        let random = catalog.get("gen9randombattle").unwrap();
+        assert_eq!(random.name, "[Gen 9] Random Battle");
+        assert!(!random.team_required);
+        assert!(random.searchable);
+
+        let custom = catalog.get("[Gen 9] Custom Game").unwrap();
+        assert!(custom.team_required);
+        assert!(!custom.searchable);
+        assert!(custom.challengeable && custom.tournament);
+
+        let vgc = catalog.get("gen8vgc2022").unwrap();
+        assert_eq!(vgc.section, "Past Gens Doubles");
+        assert!(vgc.level_50);
+        assert!(catalog.get("gen1ou").is_none());
+
+        // Old servers mark formats with suffixes
+        let catalog = FormatCatalog::parse("|Old|OU|Random Battle,#|Ubers,");
+        assert!(catalog.get("ou").unwrap().searchable);
+        assert!(!catalog.get("randombattle").unwrap().team_required);
+        assert!(!catalog.get("ubers").unwrap().searchable);
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+
+use serde_json;
+
+use battle::BattleState;
+use command::Command;
+use helpers::sanitize;
+
+/// The searches and battles of the bot, sent as `|updatesearch|JSON`
+/// whenever they change.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct Search {
+    /// The formats being searched for.
+    #[serde(default)]
+    pub searching: Vec<String>,
+    /// The battles being played, from room id to title. The server sends
+    /// `null` when there are none.
+    #[serde(default)]
+    pub games: Option<BTreeMap<String, String>>,
+}
+
+impl Search {
+    pub fn parse(json: &str) -> ::Result<Search> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Returns the number of battles being played.
+    pub fn battles(&self) -> usize {
+        self.games.as_ref().map_or(0, |g| g.len())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win,
+    Loss,
+    Tie,
+}
+
+/// The result of a battle the bot played.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BattleResult {
+    pub room: String,
+    /// The format id, e.g. `gen9randombattle`.
+    pub format: String,
+    pub opponent: String,
+    pub outcome: Outcome,
+}
+
+impl BattleResult {
+    /// Reads the result of a finished battle from its state. Returns `None`
+    /// if the battle is not over or the bot was not playing in it.
+    pub fn from_state(state: &BattleState) -> Option<BattleResult> {
+        if !state.ended {
+            return None;
+        }
+        let own = match state.request {
+            Some(ref request) => sanitize(&request.side.name),
+            None => return None,
+        };
+
+        let opponent = state.sides.values()
+            .map(|s| &s.player)
+            .find(|p| sanitize(p) != own)
+            .cloned()
+            .unwrap_or_default();
+        let outcome = match state.winner {
+            Some(ref winner) if sanitize(winner) == own => Outcome::Win,
+            Some(_) => Outcome::Loss,
+            None => Outcome::Tie,
+        };
+
+        // Battle rooms are named battle-FORMAT-NUMBER, with a password
+        // after another dash if they are hidden
+        let format = state.room.trim_left_matches("battle-")
+            .split('-')
+            .next()
+            .unwrap_or("")
+            .to_string();
+
+        Some(BattleResult {
+            room: state.room.clone(),
+            format,
+            opponent,
+            outcome,
+        })
+    }
+}
+
+/// A tally of battle results.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Record {
+    pub wins: u32,
+    pub losses: u32,
+    pub ties: u32,
+}
+
+/// Plays ladder battles in the formats from the config. Whenever the server
+/// reports that the bot is not searching and plays fewer battles than
+/// `ladder_max_battles`, it searches in the next format, in turn.
+#[derive(Clone, Debug, Default)]
+pub struct Ladder {
+    search: Search,
+    /// True once the bot is logged in and may search.
+    started: bool,
+    /// True from sending a search until the server answers with its
+    /// searches, so that one search is not sent twice.
+    waiting: bool,
+    next: usize,
+    results: Vec<BattleResult>,
+}
+
+impl Ladder {
+    pub fn new() -> Ladder {
+        Ladder::default()
+    }
+
+    /// Allows searching, once the bot is logged in, and returns the
+    /// commands for the first search if there is room for a battle.
+    pub fn start(&mut self, config: &::Config) -> Vec<Command> {
+        self.started = true;
+        self.next_search(config)
+    }
+
+    /// Stores the searches sent by the server and returns the commands for
+    /// the next search if there is room for another battle.
+    pub fn update(&mut self, search: Search, config: &::Config)
+        -> Vec<Command>
+    {
+        self.search = search;
+        self.waiting = false;
+        self.next_search(config)
+    }
+
+    fn next_search(&mut self, config: &::Config) -> Vec<Command> {
+        if !self.started || self.waiting || config.ladder_formats.is_empty() ||
+            !self.search.searching.is_empty() ||
+            self.search.battles() >= config.ladder_max_battles {
+            return Vec::new();
+        }
+
+        let formats = &config.ladder_formats;
+        let format = &formats[self.next % formats.len()];
+        self.next += 1;
+        self.waiting = true;
+
+        // Always send a team, so one from an earlier challenge is not reused
+        let team = config.ladder_teams.get(format).cloned().unwrap_or_default();
+        vec![Command::Utm(team), Command::Search(format.clone())]
+    }
+
+    /// Records the result of a finished battle the bot played.
+    pub fn finish(&mut self, state: &BattleState) -> Option<BattleResult> {
+        let result = BattleResult::from_state(state)?;
+        self.results.push(result.clone());
+        Some(result)
+    }
+
+    /// Returns the searches last sent by the server.
+    pub fn search(&self) -> &Search {
+        &self.search
+    }
+
+    /// Returns the results of the battles played so far, oldest first.
+    pub fn results(&self) -> &[BattleResult] {
+        &self.results
+    }
+
+    /// Returns the tally of the battles played so far in a format, or in all
+    /// formats.
+    pub fn record(&self, format: Option<&str>) -> Record {
+        let mut record = Record::default();
+        for result in self.results.iter()
+            .filter(|r| format.map_or(true, |f| r.format == f)) {
+            match result.outcome {
+                Outcome::Win => record.wins += 1,
+                Outcome::Loss => record.losses += 1,
+                Outcome::Tie => record.ties += 1,
+            }
+        }
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BattleResult, Ladder, Outcome, Search};
+    use battle::{BattleEvent, BattleState, Request};
+    use command::Command;
+    use config::Config;
+
+    fn config() -> Config {
+        let mut config = Config::new("examples/example_config.toml").unwrap();
+        config.ladder_formats = vec!["gen9randombattle".to_string(),
+                                     "gen9ou".to_string()];
+        config.ladder_teams.insert("gen9ou".to_string(), "TEAM".to_string());
+        config.ladder_max_battles = 2;
+        config
+    }
+
+    #[test]
+    fn search_test() {
+        let config = config();
+        let mut ladder = Ladder::new();
+
+        let idle = Search::parse(r#"{"searching":[],"games":null}"#).unwrap();
+        assert!(ladder.update(idle, &config).is_empty());
+        assert_eq!(ladder.start(&config), vec![
+            Command::Utm(String::new()),
+            Command::Search("gen9randombattle".to_string()),
+        ]);
+        // Waiting for the server to confirm the search
+        assert!(ladder.start(&config).is_empty());
+
+        let searching = Search::parse(r#"{"searching":["gen9randombattle"],
+                                          "games":null}"#).unwrap();
+        assert!(ladder.update(searching, &config).is_empty());
+
+        let one = Search::parse(r#"{"searching":[], "games":
+            {"battle-gen9randombattle-1":"[Gen 9] Random Battle"}}"#).unwrap();
+        assert_eq!(ladder.update(one, &config), vec![
+            Command::Utm("TEAM".to_string()),
+            Command::Search("gen9ou".to_string()),
+        ]);
+
+        let two = Search::parse(r#"{"searching":[], "games": {
+            "battle-gen9randombattle-1":"[Gen 9] Random Battle",
+            "battle-gen9ou-2":"[Gen 9] OU"}}"#).unwrap();
+        assert!(ladder.update(two, &config).is_empty());
+    }
+
+    #[test]
+    fn result_test() {
+        let mut state = BattleState::new("battle-gen9randombattle-12");
+        for line in &["|player|p1|Bot|1|", "|player|p2|Alice|2|"] {
+            state.apply(&BattleEvent::parse(line).unwrap().unwrap());
+        }
+        assert_eq!(BattleResult::from_state(&state), None);
+
+        state.apply(&BattleEvent::Win("Alice".to_string()));
+        assert_eq!(BattleResult::from_state(&state), None);
+
+        state.request = Some(Request::parse(
+            r#"{"wait":true,"side":{"name":"Bot","id":"p1"}}"#).unwrap());
+        let mut ladder = Ladder::new();
+        let result = ladder.finish(&state).unwrap();
+        assert_eq!(result.format, "gen9randombattle");
+        assert_eq!(result.opponent, "Alice");
+        assert_eq!(result.outcome, Outcome::Loss);
+        assert_eq!(ladder.record(Some("gen9randombattle")).losses, 1);
+        assert_eq!(ladder.record(Some("gen9ou")).losses, 0);
+    }
+}
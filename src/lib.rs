@@ -6,6 +6,7 @@
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
+extern crate native_tls;
 extern crate regex;
 extern crate reqwest;
 extern crate serde;
@@ -23,31 +24,79 @@ extern crate rand;
 extern crate rustc_serialize;
 extern crate scoped_threadpool;
 
+pub use self::battle::BattleEvent;
 pub use self::bot::Bot;
+pub use self::challenge::{Challenge, ChallengeDecision, ChallengeFilter,
+                          ChallengePolicy, Challenges};
+pub use self::command::Command;
 pub use self::config::Config;
 pub use self::error::{Error, Result};
+pub use self::format::{Format, FormatCatalog, Section};
+pub use self::ladder::{BattleResult, Ladder, Outcome, Record, Search};
 pub use self::message::Message;
+pub use self::outbox::Priority;
 pub use self::plugin::Plugin;
+pub use self::protocol::{ParseError, ServerMessage};
+pub use self::query::{Query, UserDetails};
+pub use self::rank::Rank;
+pub use self::target::{Room, Target, User};
+pub use self::team::{PokemonSet, Stats, Team};
+pub use self::tournament::{Bracket, BracketNode, MatchResult, TableCell,
+                            TableHeaders, Tournament, TournamentEnd,
+                            TournamentEvent, TournamentListener,
+                            TournamentUpdate};
+pub use self::transport::{Frame, MemoryServer, MemoryTransport, Transport,
+                          WebSocketTransport};
 
+pub mod battle;
 pub mod plugin;
 mod bot;
+mod challenge;
+mod command;
 mod config;
 mod error;
+mod format;
+mod ladder;
 mod message;
+mod outbox;
+mod protocol;
+mod query;
+mod rank;
+mod split;
 mod target;
+mod team;
+mod tournament;
+mod transport;
 
 pub mod helpers {
-    use regex::Regex;
-
-    lazy_static! {
-        static ref REGEX: Regex = Regex::new(r"[^0-9a-zA-Z]").unwrap();
-    }
+    pub use split::split_message;
 
     /// Removes non-alphanumeric characters from a string.
     /// We define non-alphanumeric as [^0-9a-zA-Z].
     ///
-    /// Returns the string in lower case to guarantee uniqueness.
+    /// Returns the string in lower case to guarantee uniqueness. Every line
+    /// from a user sanitizes a few names, so this does not use a regex.
     pub fn sanitize(s: &str) -> String {
-        REGEX.replace(s, "").into_owned().to_lowercase()
+        s.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    }
+
+    /// Escapes text so that Showdown shows it as is instead of running it.
+    /// Line breaks become spaces, since every line is parsed as a separate
+    /// message. A leading `/` is doubled, which Showdown shows as a single
+    /// `/`, and a leading `!` gets a zero width space in front so that it is
+    /// not broadcast as a command.
+    pub fn escape(s: &str) -> String {
+        let s = s.replace(|c: char| c == '\n' || c == '\r', " ");
+        let s = s.trim_left();
+        if s.starts_with('/') {
+            format!("/{}", s)
+        } else if s.starts_with('!') {
+            format!("\u{200B}{}", s)
+        } else {
+            s.to_string()
+        }
     }
 }
@@ -1,264 +1,379 @@
+use std::borrow::Cow;
 use std::sync::{Arc, Mutex};
 use time::{Tm, now};
 
+use battle::{BattleEvent, Request};
+use challenge::Challenges;
+use command::Command;
+use format::FormatCatalog;
+use ladder::Search;
+use protocol::ServerMessage;
+use rank::Rank;
 use target::{Target, User, Room};
+use tournament::TournamentEvent;
 
 /// A `Message` is a message from the server, parsed to make sense of
-/// Pokemon Showdown's custom protocol.
+/// Pokemon Showdown's custom protocol. It borrows from the frame it was
+/// received in.
 #[derive(Clone, Debug)]
 pub struct Message<'a> {
     bot: &'a Arc<Mutex<::Bot>>,
     pub received: Tm,
     pub timestamp: u32,
-    pub command: String,
-    pub params: Vec<String>,
+    pub line: &'a str,
+    pub kind: ServerMessage<'a>,
+    pub tournament: Option<TournamentEvent>,
     pub private: bool,
+    /// The id of the room as sent by the server, e.g. `battle-gen9ou-1`.
+    /// Battles are looked up by it, since `room` holds a sanitized name.
+    pub room_id: &'a str,
     pub room: Room,
     pub user: User,
-    pub auth: String,
-    pub payload: String,
+    pub rank: Rank,
+    pub away: bool,
+    pub payload: Cow<'a, str>,
 }
 
 impl<'a> Message<'a> {
-    /// Creates a new `Message` by serializing the message in text form.
-    pub fn from_string(text: String, bot: &'a Arc<Mutex<::Bot>>) -> Self {
+    /// Creates a new `Message` by parsing a protocol line sent to `room`,
+    /// which is empty for lines outside of a room. Returns an error if the
+    /// line is malformed.
+    pub fn parse(room: &'a str, line: &'a str, bot: &'a Arc<Mutex<::Bot>>)
+        -> ::Result<Self>
+    {
         let received = now();
-
-        let nl_delim: Vec<&str> = text.split("\n").collect();
-        let vb_delim: Vec<&str> = text.split("|").collect();
-
-        // The command is always after the first vertical bar
-        let mut command = String::new();
-        if vb_delim.len() > 1 {
-            command = String::from(vb_delim[1]).to_lowercase();
-        }
-
-        // Parse the parameters following a command
-        let mut params: Vec<String> = Vec::new();
-        if !command.is_empty() && vb_delim.len() > 2 {
-            for s in &vb_delim[2..] {
-                params.push(String::from(&**s));
-            }
-        }
-
-        // Parse the UNIX timestamp of a chat event
-        let mut timestamp: u32 = 0;
-        if command.contains(":") {
-            timestamp = match params[0].parse::<u32>() {
-                Ok(i) => i,
-                Err(e) => {
-                    error!("Message From Text: {:?}", e);
-                    0
-                }
-            };
-        }
-
-        // If the message starts with a ">" then it comes from a room
-        let mut room = String::new();
-        if nl_delim.len() > 0 {
-            if !nl_delim.first().unwrap().is_empty() {
-                if nl_delim.first().unwrap().as_bytes()[0] == 62u8 {
-                    room =  String::from(&nl_delim[0][1..]);
-                }
-            }
-        }
-
-        // Parse the user sending a command, and their auth level, and if the
-        // message was private, and the payload
-        let mut auth = String::new();
-        let mut user = String::new();
-        let mut payload = String::new();
+        let kind = ServerMessage::parse(line)?;
+        // A tournament event that fails to parse should not keep the line
+        // from the handlers and plugins
+        let tournament = TournamentEvent::parse(line).unwrap_or_else(|e| {
+            warn!("Failed to parse tournament event: {}", e);
+            None
+        });
+
+        // Parse the user sending a command, their rank and status, and if
+        // the message was private, and the payload
+        let mut rank = Rank::Regular;
+        let mut away = false;
+        let mut user = "";
+        let mut payload = Cow::Borrowed("");
         let mut private = false;
-        match &*command {
-            "c:" => {
-                auth = String::from(&vb_delim[3][..0]);
-                user = String::from(&vb_delim[3][1..]);
-                payload = vb_delim[4..].join("|");
+        let mut timestamp: u32 = 0;
+        match kind {
+            ServerMessage::ChatTimestamped { timestamp: t, user: ref u,
+                                             ref message } => {
+                let (r, u, a) = Rank::split_user(u);
+                rank = r;
+                user = u;
+                away = a;
+                payload = message.clone();
+                timestamp = t;
+            },
+            ServerMessage::Chat { user: ref u, ref message } => {
+                let (r, u, a) = Rank::split_user(u);
+                rank = r;
+                user = u;
+                away = a;
+                payload = message.clone();
             },
-            "pm" => {
-                auth = String::from(&vb_delim[2][..0]);
-                user = String::from(&vb_delim[2][1..]);
-                payload = vb_delim[4..].join("|");
+            ServerMessage::Pm { ref sender, ref message, .. } => {
+                let (r, u, a) = Rank::split_user(sender);
+                rank = r;
+                user = u;
+                away = a;
+                payload = message.clone();
                 private = true;
-            }
-            "c" | "j" | "l" | "n" => {
-                auth = String::from(&vb_delim[2][..0]);
-                user = String::from(&vb_delim[2][1..]);
-            }
+            },
+            ServerMessage::Join(ref u) |
+            ServerMessage::Leave(ref u) |
+            ServerMessage::Name { user: ref u, .. } => {
+                let (r, u, a) = Rank::split_user(u);
+                rank = r;
+                user = u;
+                away = a;
+            },
+            ServerMessage::Timestamp(t) => {
+                timestamp = t;
+                payload = Cow::Borrowed(line);
+            },
             _ => {
-                payload = String::from(&**nl_delim.last().unwrap());
+                payload = Cow::Borrowed(line);
             }
         }
 
         // Update state
         // TODO: This shouldn't be here. do when necessary in j/l/n events.
-        if !room.is_empty() {
-            bot.lock().unwrap().room_map.insert(&room);
-        }
-        if !user.is_empty() {
-            bot.lock().unwrap().user_map.insert(&user);
-        }
-        if !(user.is_empty() || room.is_empty()) {
-            bot.lock().unwrap()
-                .room_map.insert_user_in_room(&user, &room);
-            bot.lock().unwrap()
-                .user_map.add_auth_to_user_in_room(&auth, &user, &room);
+        // The bot is only locked once, and only for lines that change it.
+        // Each of the updates inserts the room or user it is missing.
+        if !(room.is_empty() && user.is_empty()) {
+            let mut b = bot.lock().unwrap();
+            if user.is_empty() {
+                b.room_map.insert(room);
+            } else {
+                b.user_map.set_user_away(user, away);
+                if !room.is_empty() {
+                    b.room_map.insert_user_in_room(user, room);
+                    b.user_map.set_rank_of_user_in_room(rank, user, room);
+                }
+            }
         }
 
-        Message {
+        let user = Target::new(user);
+        Ok(Message {
             bot,
             received,
             timestamp,
-            command,
-            params,
+            line,
+            kind,
+            tournament,
             private,
-            room: Target::new(&room),
-            user: Target::new(&user),
-            auth,
+            room_id: room,
+            room: Target::new(room),
+            user,
+            rank,
+            away,
             payload,
-        }
+        })
     }
 
     /// Handles server messages.
     pub fn handle(&self, bot: &'a Arc<Mutex<::Bot>>) -> ::Result<()> {
-        match &*self.command {
+        // Battle events copy the line, so they are only parsed in the battle
+        // rooms the bot is in
+        if bot.lock().unwrap().tracks_battle(self.room_id) {
+            if let Some(event) = self.battle() {
+                bot.lock().unwrap().update_battle(self.room_id, &event);
+            }
+        }
+
+        match self.kind {
             // |battle|ROOMID|USER1|USER2 or |b|ROOMID|USER1|USER2
-            "b" | "battle" => Ok(()),
+            ServerMessage::Battle { .. } => Ok(()),
 
             // |challstr|CHALLSTR
-            "challstr" => {
-                info!("Attempting to log in...");
-                bot.lock().unwrap().login(
-                    &format!("{}|{}", &self.params[0], &self.params[1]))?;
+            ServerMessage::Challstr(ref challstr) => {
+                let config = bot.lock().unwrap().config.clone();
+                if !config.guest {
+                    info!("Attempting to log in...");
+                    bot.lock().unwrap().login(challstr)?;
+                    return Ok(());
+                }
+
+                // Guests never hear back with a named updateuser unless they
+                // pick a name, so join the rooms straight away
+                match config.guest_name {
+                    Some(ref name) => {
+                        info!("Continuing as guest {}", name);
+                        bot.lock().unwrap().send_command(
+                            &Command::Trn(name.clone(), String::new()), "");
+                    },
+                    None => {
+                        info!("Continuing as guest");
+                        bot.lock().unwrap().set_logged_in();
+                        bot.lock().unwrap().join_rooms();
+                    },
+                }
                 Ok(())
             },
 
             // |c:|TIMESTAMP|USER|MESSAGE
             // Chat events are handled in the receive loop.
-            "c:" => Ok(()),
+            ServerMessage::ChatTimestamped { .. } => Ok(()),
+
+            // |deinit
+            ServerMessage::Deinit => {
+                let b = bot.lock().unwrap();
+                b.remove_battle(self.room_id);
+                b.remove_tournament(&self.room.name);
+                Ok(())
+            },
 
             // |formats|FORMATSLIST
-            "formats" => Ok(()),
+            ServerMessage::Formats(ref list) => {
+                bot.lock().unwrap().set_formats(FormatCatalog::parse(list));
+                Ok(())
+            },
 
             // |html|HTML
-            "html" => Ok(()),
+            ServerMessage::Html(_) => Ok(()),
 
             // |init|ROOMTYPE
-            "init" => Ok(()),
+            ServerMessage::Init(ref room_type) => {
+                if room_type == "battle" {
+                    bot.lock().unwrap().track_battle(self.room_id);
+                }
+                Ok(())
+            },
 
             // |join|USER or |j|USER
-            "j" | "join" => {
+            ServerMessage::Join(_) => {
                 bot.lock().unwrap().room_map
                     .insert_user_in_room(&self.user.name, &self.room.name);
                 bot.lock().unwrap().user_map
-                    .add_auth_to_user_in_room(
-                        &self.auth, &self.user.name, &self.room.name);
+                    .set_rank_of_user_in_room(
+                        self.rank, &self.user.name, &self.room.name);
                 Ok(())
             },
 
             // |leave|USER or |l|USER
-            "l" | "leave" => {
+            ServerMessage::Leave(_) => {
                 bot.lock().unwrap().room_map
                     .remove_user_from_room(&self.user.name, &self.room.name);
                 Ok(())
             },
 
             // ||MESSAGE or MESSAGE
-            "" => Ok(()),
+            ServerMessage::Message(_) => Ok(()),
 
             // |nametaken|USERNAME|MESSAGE
-            "nametaken" => Ok(()),
+            // A guest that could not take its name keeps the one it was
+            // given, since no named updateuser will follow
+            ServerMessage::NameTaken { ref user, ref message } => {
+                let mut b = bot.lock().unwrap();
+                if b.config.guest && !b.is_logged_in() {
+                    warn!("Could not rename to {}: {}", user, message);
+                    info!("Continuing as guest");
+                    b.set_logged_in();
+                    b.join_rooms();
+                }
+                Ok(())
+            },
 
             // |name|USER|OLDID or |n|USER|OLDID
-            "n" | "name" => {
+            ServerMessage::Name { ref old_id, .. } => {
                 bot.lock().unwrap().room_map
-                    .remove_user_from_room(&self.params[0], &self.room.name);
+                    .remove_user_from_room(old_id, &self.room.name);
                 bot.lock().unwrap().room_map
                     .insert_user_in_room(&self.user.name, &self.room.name);
                 bot.lock().unwrap().user_map
-                    .add_auth_to_user_in_room(
-                        &self.auth, &self.user.name, &self.room.name);
+                    .set_rank_of_user_in_room(
+                        self.rank, &self.user.name, &self.room.name);
                 Ok(())
             },
 
             // |popup|MESSAGE
-            "popup" => Ok(()),
+            ServerMessage::Popup(_) => Ok(()),
 
             // |pm|SENDER|RECEIVER|MESSAGE
-            "pm" => Ok(()),
+            ServerMessage::Pm { .. } => Ok(()),
 
             // |queryresponse|QUERYTYPE|JSON
-            "queryresponse" => Ok(()),
+            // Answers are handed to queries in the receive loop.
+            ServerMessage::QueryResponse { .. } => Ok(()),
+
+            // |request|JSON
+            ServerMessage::Request(ref json) => {
+                // An empty request is sent when the battle is over
+                if json.is_empty() {
+                    return Ok(());
+                }
+                match Request::parse(json) {
+                    Ok(request) => bot.lock().unwrap()
+                        .set_battle_request(self.room_id, request),
+                    Err(e) => warn!("Failed to parse request: {}", e),
+                }
+                Ok(())
+            },
 
             // |tie
-            "tie" => Ok(()),
+            ServerMessage::Tie => {
+                bot.lock().unwrap().finish_battle(self.room_id);
+                Ok(())
+            },
 
             // |:|TIMESTAMP
-            ":" => {
-                bot.lock().unwrap().set_login_time(self.timestamp);
+            ServerMessage::Timestamp(timestamp) => {
+                bot.lock().unwrap().set_login_time(timestamp);
+                Ok(())
+            },
+
+            // |tournament|COMMAND|...
+            ServerMessage::Tournament { .. } => {
+                if let Some(ref event) = self.tournament {
+                    ::Bot::update_tournament(bot, &self.room.name, event);
+                }
                 Ok(())
             },
 
             // |uhtml|NAME|HTML
-            "uhtml" => Ok(()),
+            ServerMessage::Uhtml { .. } => Ok(()),
 
             // |uhtmlchange|NAME|HTML
-            "uhtmlchange" => Ok(()),
+            ServerMessage::UhtmlChange { .. } => Ok(()),
 
             // |updatechallenges|JSON
-            "updatechallenges" => Ok(()),
+            ServerMessage::UpdateChallenges(ref json) => {
+                match Challenges::parse(json) {
+                    Ok(challenges) => ::Bot::update_challenges(bot, challenges),
+                    Err(e) => warn!("Failed to parse challenges: {}", e),
+                }
+                Ok(())
+            },
 
             // |updatesearch|JSON
-            "updatesearch" => Ok(()),
+            ServerMessage::UpdateSearch(ref json) => {
+                match Search::parse(json) {
+                    Ok(search) => bot.lock().unwrap().update_search(search),
+                    Err(e) => warn!("Failed to parse search: {}", e),
+                }
+                Ok(())
+            },
 
             // |updateuser|USERNAME|NAMED|AVATAR
-            "updateuser" => match &*self.params[1] {
-                "0" => {
-                    let avatar = bot.lock().unwrap()
-                        .config.avatar;
-                    if avatar > 0 && avatar <= 294 {
-                        bot.lock().unwrap()
-                            .send(format!("|/avatar {}", avatar));
-                    }
-                    Ok(())
-                },
-                "1" => {
-                    let config = bot.lock().unwrap().config.clone();
-                    for r in &config.rooms {
-                        bot.lock().unwrap().join_room(&r);
-                    }
-                    // TODO: start timed plugins
-                    Ok(())
-                },
-                _ => {
-                    unreachable!();
+            ServerMessage::UpdateUser { named: false, .. } => {
+                let avatar = bot.lock().unwrap()
+                    .config.avatar;
+                if avatar > 0 && avatar <= 294 {
+                    bot.lock().unwrap()
+                        .send_command(&Command::Avatar(avatar), "");
                 }
+                Ok(())
+            },
+            ServerMessage::UpdateUser { named: true, .. } => {
+                bot.lock().unwrap().set_logged_in();
+                bot.lock().unwrap().join_rooms();
+                bot.lock().unwrap().start_ladder();
+                // TODO: start timed plugins
+                Ok(())
             },
 
             // |usercount|USERCOUNT
-            "usercount" => Ok(()),
+            ServerMessage::UserCount(_) => Ok(()),
 
             // |users|USERLIST
-            "users" => {
-                for user in self.params[0].split(",").skip(1) {
-                    let auth: String = user.chars().take(1).collect();
-                    let user: String = user.chars().skip(1).collect();
+            ServerMessage::Users(ref users) => {
+                for user in users {
+                    let (rank, user, away) = Rank::split_user(user);
                     bot.lock().unwrap().room_map
-                        .insert_user_in_room(&user, &self.room.name);
+                        .insert_user_in_room(user, &self.room.name);
                     bot.lock().unwrap().user_map
-                        .add_auth_to_user_in_room(&auth, &user, &self.room.name);
+                        .set_rank_of_user_in_room(rank, user, &self.room.name);
+                    bot.lock().unwrap().user_map.set_user_away(user, away);
                 }
                 Ok(())
             },
 
             // |win|USER
-            "win" => Ok(()),
+            ServerMessage::Win(_) => {
+                bot.lock().unwrap().finish_battle(self.room_id);
+                Ok(())
+            },
 
             // Ignore commands we have no plan for
             _ => Ok(())
         }
     }
 
+    /// Parses the battle event on this line, if it is one.
+    pub fn battle(&self) -> Option<BattleEvent> {
+        match BattleEvent::parse(self.line) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Failed to parse battle event: {}", e);
+                None
+            },
+        }
+    }
+
     pub fn reply<S: Into<String>>(&self, text: S) {
         let msg = &format!("({}) {}", self.user.name, text.into());
         match self.private {
@@ -274,6 +389,25 @@ impl<'a> Message<'a> {
         }
     }
 
+    /// Sends a trusted command where the message came from. Unlike `send`,
+    /// the command is not escaped, so never build it from user input.
+    pub fn send_command(&self, command: &Command) {
+        match self.private {
+            false => { self.room.send_command(self.bot, command); },
+            true  => { self.user.send_command(self.bot, command); }
+        }
+    }
+
+    /// Returns the bot that received this message.
+    pub fn bot(&self) -> &Arc<Mutex<::Bot>> {
+        self.bot
+    }
+
+    /// Returns the number of chat messages the bot has waiting to be sent.
+    pub fn queue_depth(&self) -> usize {
+        self.bot.lock().unwrap().queue_depth()
+    }
+
     pub fn prefix_string(&self) -> String {
         self.bot.lock().unwrap().config.prefix_string()
     }
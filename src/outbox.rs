@@ -0,0 +1,308 @@
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use transport::Frame;
+
+/// A token bucket limiting how fast chat messages are sent. Showdown lets a
+/// user send a short burst of messages, after which it only accepts one
+/// message per throttle delay. Going over the limit gets messages dropped,
+/// so the bot mirrors it client side.
+#[derive(Debug)]
+pub struct RateLimiter {
+    burst: f64,
+    interval_ms: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a new `RateLimiter` that allows `burst` messages at once and
+    /// then one every `interval_ms`. An interval of 0 disables the limit.
+    pub fn new(burst: u32, interval_ms: u64) -> RateLimiter {
+        let burst = cmp::max(burst, 1) as f64;
+        RateLimiter {
+            burst,
+            interval_ms: interval_ms as f64,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    /// Creates the `RateLimiter` matching the account type in the config.
+    pub fn from_config(config: &::Config) -> RateLimiter {
+        let interval_ms = if config.trusted {
+            config.trusted_throttle_ms
+        } else {
+            config.throttle_ms
+        };
+        RateLimiter::new(config.throttle_burst, interval_ms)
+    }
+
+    /// Takes a token if one is available. Otherwise returns how long to wait
+    /// until one is.
+    pub fn try_take(&mut self) -> Option<Duration> {
+        if self.interval_ms <= 0.0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last);
+        let elapsed_ms = elapsed.as_secs() as f64 * 1000.0 +
+            elapsed.subsec_nanos() as f64 / 1_000_000.0;
+        self.tokens = (self.tokens + elapsed_ms / self.interval_ms)
+            .min(self.burst);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            let wait_ms = ((1.0 - self.tokens) * self.interval_ms).ceil();
+            Some(Duration::from_millis(wait_ms as u64))
+        }
+    }
+}
+
+/// How urgently a chat message should be sent. Messages are sent from the
+/// most urgent queue first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Moderation,
+    Reply,
+    Announcement,
+}
+
+/// The queue of frames waiting to be sent. Pings and pongs skip the queue
+/// and are never rate limited. Chat messages wait on the `RateLimiter` and
+/// are sent by `Priority`, taking turns between rooms within a priority so
+/// that one busy room cannot hold up the others. A close frame goes out
+/// before anything else, as the connection is gone or going anyway, unless
+/// it was queued with `close_when_sent`.
+#[derive(Debug)]
+pub struct Outbox {
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    control: VecDeque<Frame>,
+    queues: BTreeMap<Priority, RoomQueues>,
+    close: bool,
+    /// Close once every queued chat message is sent.
+    drain: bool,
+}
+
+/// Messages of one priority, queued per room. `order` holds every room with
+/// messages waiting, in the order they get their next turn.
+#[derive(Debug, Default)]
+struct RoomQueues {
+    order: VecDeque<String>,
+    rooms: HashMap<String, VecDeque<String>>,
+}
+
+impl RoomQueues {
+    fn push(&mut self, room: String, text: String) {
+        let queue = self.rooms.entry(room.clone()).or_insert_with(VecDeque::new);
+        if queue.is_empty() {
+            self.order.push_back(room);
+        }
+        queue.push_back(text);
+    }
+
+    fn pop(&mut self) -> Option<String> {
+        let room = self.order.pop_front()?;
+        let (text, empty) = {
+            let queue = self.rooms.get_mut(&room).unwrap();
+            (queue.pop_front().unwrap(), queue.is_empty())
+        };
+        if empty {
+            self.rooms.remove(&room);
+        } else {
+            self.order.push_back(room);
+        }
+        Some(text)
+    }
+
+    fn len(&self) -> usize {
+        self.rooms.values().map(|q| q.len()).sum()
+    }
+}
+
+impl State {
+    fn pending(&self) -> usize {
+        self.queues.values().map(|q| q.len()).sum()
+    }
+
+    fn pop_text(&mut self) -> Option<String> {
+        self.queues.values_mut().filter_map(|q| q.pop()).next()
+    }
+}
+
+impl Outbox {
+    pub fn new() -> Outbox {
+        Outbox {
+            state: Mutex::new(State::default()),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Queues a frame to be sent, with chat messages sent as replies.
+    pub fn push(&self, frame: Frame) {
+        self.push_with_priority(frame, Priority::Reply);
+    }
+
+    /// Queues a frame to be sent. `priority` only applies to chat messages.
+    pub fn push_with_priority(&self, frame: Frame, priority: Priority) {
+        let mut state = self.state.lock().unwrap();
+        match frame {
+            Frame::Text(text) => {
+                let room = match text.find('|') {
+                    Some(i) => text[..i].to_string(),
+                    None => String::new(),
+                };
+                state.queues.entry(priority)
+                    .or_insert_with(RoomQueues::default)
+                    .push(room, text);
+            },
+            Frame::Close => state.close = true,
+            _ => state.control.push_back(frame),
+        }
+        self.ready.notify_one();
+    }
+
+    /// Queues a close frame to be sent once the chat messages queued so far
+    /// are out.
+    pub fn close_when_sent(&self) {
+        self.state.lock().unwrap().drain = true;
+        self.ready.notify_one();
+    }
+
+    /// Blocks until the next frame may be sent and returns it.
+    pub fn pop(&self, limiter: &mut RateLimiter) -> Frame {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.close {
+                state.close = false;
+                state.drain = false;
+                return Frame::Close;
+            }
+
+            if let Some(frame) = state.control.pop_front() {
+                return frame;
+            }
+
+            if state.pending() == 0 {
+                if state.drain {
+                    state.drain = false;
+                    return Frame::Close;
+                }
+                state = self.ready.wait(state).unwrap();
+                continue;
+            }
+
+            match limiter.try_take() {
+                None => return Frame::Text(state.pop_text().unwrap()),
+                Some(timeout) => {
+                    state = self.ready.wait_timeout(state, timeout).unwrap().0;
+                },
+            }
+        }
+    }
+
+    /// Returns the number of chat messages waiting to be sent.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().pending()
+    }
+
+    /// Drops every queued frame and returns how many there were. A close
+    /// asked for with `close_when_sent` is kept.
+    pub fn clear(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let count = state.control.len() + state.pending();
+        state.control.clear();
+        state.queues.clear();
+        state.close = false;
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outbox, Priority, RateLimiter};
+    use transport::Frame;
+
+    #[test]
+    fn rate_limiter_test() {
+        let mut limiter = RateLimiter::new(3, 60000);
+        assert!(limiter.try_take().is_none());
+        assert!(limiter.try_take().is_none());
+        assert!(limiter.try_take().is_none());
+        assert!(limiter.try_take().is_some());
+
+        let mut unlimited = RateLimiter::new(1, 0);
+        for _ in 0..10 {
+            assert!(unlimited.try_take().is_none());
+        }
+    }
+
+    #[test]
+    fn control_frames_skip_queue_test() {
+        let outbox = Outbox::new();
+        let mut limiter = RateLimiter::new(1, 0);
+        outbox.push(Frame::Text("a".to_string()));
+        outbox.push(Frame::Pong(Vec::new()));
+        outbox.close_when_sent();
+        assert_eq!(outbox.len(), 1);
+
+        assert_eq!(outbox.pop(&mut limiter), Frame::Pong(Vec::new()));
+        assert_eq!(outbox.pop(&mut limiter), Frame::Text("a".to_string()));
+        assert_eq!(outbox.pop(&mut limiter), Frame::Close);
+        assert_eq!(outbox.len(), 0);
+    }
+
+    #[test]
+    fn close_first_test() {
+        let outbox = Outbox::new();
+        let mut limiter = RateLimiter::new(1, 60000);
+        for text in &["a|1", "a|2", "b|1"] {
+            outbox.push(Frame::Text(text.to_string()));
+        }
+        assert_eq!(outbox.pop(&mut limiter), Frame::Text("a|1".to_string()));
+
+        // The limiter is out of tokens, but the close does not wait on it
+        outbox.push(Frame::Pong(Vec::new()));
+        outbox.push(Frame::Close);
+        assert_eq!(outbox.pop(&mut limiter), Frame::Close);
+        assert_eq!(outbox.clear(), 3);
+
+        // Clearing a dead connection's frames keeps a deliberate close
+        outbox.push(Frame::Text("a|3".to_string()));
+        outbox.close_when_sent();
+        assert_eq!(outbox.clear(), 1);
+        assert_eq!(outbox.pop(&mut limiter), Frame::Close);
+    }
+
+    #[test]
+    fn priority_and_fairness_test() {
+        let outbox = Outbox::new();
+        let mut limiter = RateLimiter::new(1, 0);
+        for text in &["a|1", "a|2", "a|3", "b|1"] {
+            outbox.push(Frame::Text(text.to_string()));
+        }
+        outbox.push_with_priority(
+            Frame::Text("c|1".to_string()), Priority::Announcement);
+        outbox.push_with_priority(
+            Frame::Text("a|/mute x".to_string()), Priority::Moderation);
+        assert_eq!(outbox.len(), 6);
+
+        let sent: Vec<Frame> = (0..6).map(|_| outbox.pop(&mut limiter)).collect();
+        let expected: Vec<Frame> =
+            ["a|/mute x", "a|1", "b|1", "a|2", "a|3", "c|1"].iter()
+            .map(|t| Frame::Text(t.to_string()))
+            .collect();
+        assert_eq!(sent, expected);
+    }
+}
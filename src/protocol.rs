@@ -0,0 +1,434 @@
+use std::borrow::Cow;
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+/// A single line of the server protocol, without the `>ROOM` header of the
+/// frame it came in. Users are kept as sent, including the rank character in
+/// front of the name.
+///
+/// Parsing borrows from the line, so nothing is allocated until the message
+/// is turned into an owned one with `into_owned`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage<'a> {
+    /// `|c|USER|MESSAGE` or `|chat|USER|MESSAGE`
+    Chat { user: Cow<'a, str>, message: Cow<'a, str> },
+    /// `|c:|TIMESTAMP|USER|MESSAGE`
+    ChatTimestamped { timestamp: u32, user: Cow<'a, str>, message: Cow<'a, str> },
+    /// `|pm|SENDER|RECEIVER|MESSAGE`
+    Pm { sender: Cow<'a, str>, receiver: Cow<'a, str>, message: Cow<'a, str> },
+    /// `|join|USER`, `|j|USER` or `|J|USER`
+    Join(Cow<'a, str>),
+    /// `|leave|USER`, `|l|USER` or `|L|USER`
+    Leave(Cow<'a, str>),
+    /// `|name|USER|OLDID`, `|n|USER|OLDID` or `|N|USER|OLDID`
+    Name { user: Cow<'a, str>, old_id: Cow<'a, str> },
+    /// `|init|ROOMTYPE`
+    Init(Cow<'a, str>),
+    /// `|deinit`
+    Deinit,
+    /// `|title|TITLE`
+    Title(Cow<'a, str>),
+    /// `|users|USERLIST`, without the user count at the front of the list.
+    Users(Vec<Cow<'a, str>>),
+    /// `|usercount|USERCOUNT`
+    UserCount(u32),
+    /// `|raw|HTML`
+    Raw(Cow<'a, str>),
+    /// `|html|HTML`
+    Html(Cow<'a, str>),
+    /// `|uhtml|NAME|HTML`
+    Uhtml { name: Cow<'a, str>, html: Cow<'a, str> },
+    /// `|uhtmlchange|NAME|HTML`
+    UhtmlChange { name: Cow<'a, str>, html: Cow<'a, str> },
+    /// `|popup|MESSAGE`
+    Popup(Cow<'a, str>),
+    /// `|updateuser|USER|NAMED|AVATAR|SETTINGS`
+    UpdateUser {
+        user: Cow<'a, str>,
+        named: bool,
+        avatar: Cow<'a, str>,
+        settings: Cow<'a, str>,
+    },
+    /// `|nametaken|USERNAME|MESSAGE`
+    NameTaken { user: Cow<'a, str>, message: Cow<'a, str> },
+    /// `|queryresponse|QUERYTYPE|JSON`
+    QueryResponse { query_type: Cow<'a, str>, json: Cow<'a, str> },
+    /// `|challstr|CHALLSTR`
+    Challstr(Cow<'a, str>),
+    /// `|:|TIMESTAMP`
+    Timestamp(u32),
+    /// `|formats|FORMATSLIST`, kept as the raw list.
+    Formats(Cow<'a, str>),
+    /// `|updatechallenges|JSON`
+    UpdateChallenges(Cow<'a, str>),
+    /// `|updatesearch|JSON`
+    UpdateSearch(Cow<'a, str>),
+    /// `|tournament|COMMAND|PARAMS...`
+    Tournament { command: Cow<'a, str>, params: Vec<Cow<'a, str>> },
+    /// `|battle|ROOMID|USER1|USER2` or `|b|ROOMID|USER1|USER2`
+    Battle { room: Cow<'a, str>, user1: Cow<'a, str>, user2: Cow<'a, str> },
+    /// `|request|JSON`
+    Request(Cow<'a, str>),
+    /// `|win|USER`
+    Win(Cow<'a, str>),
+    /// `|tie`
+    Tie,
+    /// `||MESSAGE` or a line without a command.
+    Message(Cow<'a, str>),
+    /// Any other command.
+    Unknown { command: Cow<'a, str>, params: Vec<Cow<'a, str>> },
+}
+
+/// An error from parsing a protocol line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    /// The command is missing the parameter at `index`.
+    MissingParam { command: String, index: usize },
+    /// The parameter at `index` is not valid for the command.
+    InvalidParam { command: String, index: usize, value: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::MissingParam { ref command, index } =>
+                write!(f, "|{}| is missing parameter {}", command, index),
+            ParseError::InvalidParam { ref command, index, ref value } =>
+                write!(f, "|{}| has invalid parameter {}: {:?}",
+                       command, index, value),
+        }
+    }
+}
+
+impl StdError for ParseError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseError::MissingParam { .. } => "missing protocol parameter",
+            ParseError::InvalidParam { .. } => "invalid protocol parameter",
+        }
+    }
+}
+
+/// The parameters of a protocol line, split on `|`.
+pub struct Params<'a> {
+    line: &'a str,
+    pub command: &'a str,
+    pub params: Vec<&'a str>,
+}
+
+impl<'a> Params<'a> {
+    /// Splits a line starting with `|` into its command and parameters.
+    pub fn new(line: &'a str) -> Params<'a> {
+        let body = if line.starts_with('|') { &line[1..] } else { line };
+        let mut parts = body.splitn(2, '|');
+        let command = parts.next().unwrap_or("");
+        let params = match parts.next() {
+            Some(rest) => rest.split('|').collect(),
+            None => Vec::new(),
+        };
+        Params { line, command, params }
+    }
+
+    pub fn get(&self, index: usize) -> Result<&'a str, ParseError> {
+        match self.params.get(index) {
+            Some(p) => Ok(*p),
+            None => Err(ParseError::MissingParam {
+                command: self.command.to_string(),
+                index,
+            }),
+        }
+    }
+
+    pub fn string(&self, index: usize) -> Result<Cow<'a, str>, ParseError> {
+        self.get(index).map(Cow::Borrowed)
+    }
+
+    /// The rest of the line from the parameter at `index` on. Used for the
+    /// last parameter of commands where it may itself contain `|`.
+    pub fn rest(&self, index: usize) -> Result<Cow<'a, str>, ParseError> {
+        let param = self.get(index)?;
+        let start = param.as_ptr() as usize - self.line.as_ptr() as usize;
+        Ok(Cow::Borrowed(&self.line[start..]))
+    }
+
+    /// The parameter at `index`, or an empty string if it is missing.
+    pub fn optional(&self, index: usize) -> Cow<'a, str> {
+        self.get(index).map(Cow::Borrowed).unwrap_or_default()
+    }
+
+    pub fn number<T: FromStr>(&self, index: usize) -> Result<T, ParseError> {
+        let value = self.get(index)?;
+        value.trim().parse().map_err(|_| ParseError::InvalidParam {
+            command: self.command.to_string(),
+            index,
+            value: value.to_string(),
+        })
+    }
+
+    pub fn list(&self, index: usize) -> Vec<Cow<'a, str>> {
+        self.params.iter().skip(index).map(|p| Cow::Borrowed(*p)).collect()
+    }
+
+    /// Parses the parameter at `index` with `f`, which returns `None` if
+    /// the parameter is not valid.
+    pub fn with<T, F>(&self, index: usize, f: F) -> Result<T, ParseError>
+        where F: FnOnce(&'a str) -> Option<T>
+    {
+        let value = self.get(index)?;
+        f(value).ok_or_else(|| ParseError::InvalidParam {
+            command: self.command.to_string(),
+            index,
+            value: value.to_string(),
+        })
+    }
+}
+
+impl<'a> ServerMessage<'a> {
+    /// Parses a single protocol line. The `>ROOM` header of a frame must
+    /// already be removed.
+    pub fn parse(line: &'a str) -> Result<ServerMessage<'a>, ParseError> {
+        if !line.starts_with('|') {
+            return Ok(ServerMessage::Message(Cow::Borrowed(line)));
+        }
+
+        let p = Params::new(line);
+        let command = p.command;
+
+        let message = match command {
+            "" => ServerMessage::Message(p.rest(0).unwrap_or_default()),
+            "c" | "chat" => ServerMessage::Chat {
+                user: p.string(0)?,
+                message: p.rest(1)?,
+            },
+            "c:" => ServerMessage::ChatTimestamped {
+                timestamp: p.number(0)?,
+                user: p.string(1)?,
+                message: p.rest(2)?,
+            },
+            "pm" => ServerMessage::Pm {
+                sender: p.string(0)?,
+                receiver: p.string(1)?,
+                message: p.rest(2)?,
+            },
+            "j" | "J" | "join" => ServerMessage::Join(p.string(0)?),
+            "l" | "L" | "leave" => ServerMessage::Leave(p.string(0)?),
+            "n" | "N" | "name" => ServerMessage::Name {
+                user: p.string(0)?,
+                old_id: p.string(1)?,
+            },
+            "init" => ServerMessage::Init(p.string(0)?),
+            "deinit" => ServerMessage::Deinit,
+            "title" => ServerMessage::Title(p.rest(0)?),
+            "users" => ServerMessage::Users(
+                p.get(0)?.split(',').skip(1).map(Cow::Borrowed).collect()),
+            "usercount" => ServerMessage::UserCount(p.number(0)?),
+            "raw" => ServerMessage::Raw(p.rest(0)?),
+            "html" => ServerMessage::Html(p.rest(0)?),
+            "uhtml" => ServerMessage::Uhtml {
+                name: p.string(0)?,
+                html: p.rest(1)?,
+            },
+            "uhtmlchange" => ServerMessage::UhtmlChange {
+                name: p.string(0)?,
+                html: p.rest(1)?,
+            },
+            "popup" => ServerMessage::Popup(p.rest(0)?),
+            "updateuser" => ServerMessage::UpdateUser {
+                user: p.string(0)?,
+                named: match p.get(1)? {
+                    "0" => false,
+                    "1" => true,
+                    value => return Err(ParseError::InvalidParam {
+                        command: command.to_string(),
+                        index: 1,
+                        value: value.to_string(),
+                    }),
+                },
+                avatar: p.optional(2),
+                settings: p.optional(3),
+            },
+            "nametaken" => ServerMessage::NameTaken {
+                user: p.string(0)?,
+                message: p.rest(1).unwrap_or_default(),
+            },
+            "queryresponse" => ServerMessage::QueryResponse {
+                query_type: p.string(0)?,
+                json: p.rest(1)?,
+            },
+            "challstr" => ServerMessage::Challstr(p.rest(0)?),
+            ":" => ServerMessage::Timestamp(p.number(0)?),
+            "formats" => ServerMessage::Formats(p.rest(0).unwrap_or_default()),
+            "updatechallenges" => ServerMessage::UpdateChallenges(p.rest(0)?),
+            "updatesearch" => ServerMessage::UpdateSearch(p.rest(0)?),
+            "tournament" => ServerMessage::Tournament {
+                command: p.string(0)?,
+                params: p.list(1),
+            },
+            "b" | "battle" => ServerMessage::Battle {
+                room: p.string(0)?,
+                user1: p.string(1)?,
+                user2: p.string(2)?,
+            },
+            "request" => ServerMessage::Request(p.rest(0).unwrap_or_default()),
+            "win" => ServerMessage::Win(p.string(0)?),
+            "tie" => ServerMessage::Tie,
+            _ => ServerMessage::Unknown {
+                command: Cow::Borrowed(command),
+                params: p.list(0),
+            },
+        };
+
+        Ok(message)
+    }
+
+    /// Copies any borrowed text so that the message can outlive the line it
+    /// was parsed from.
+    pub fn into_owned(self) -> ServerMessage<'static> {
+        use self::ServerMessage::*;
+
+        match self {
+            Chat { user, message } =>
+                Chat { user: owned(user), message: owned(message) },
+            ChatTimestamped { timestamp, user, message } =>
+                ChatTimestamped {
+                    timestamp,
+                    user: owned(user),
+                    message: owned(message),
+                },
+            Pm { sender, receiver, message } => Pm {
+                sender: owned(sender),
+                receiver: owned(receiver),
+                message: owned(message),
+            },
+            Join(user) => Join(owned(user)),
+            Leave(user) => Leave(owned(user)),
+            Name { user, old_id } =>
+                Name { user: owned(user), old_id: owned(old_id) },
+            Init(room_type) => Init(owned(room_type)),
+            Deinit => Deinit,
+            Title(title) => Title(owned(title)),
+            Users(users) => Users(users.into_iter().map(owned).collect()),
+            UserCount(count) => UserCount(count),
+            Raw(html) => Raw(owned(html)),
+            Html(html) => Html(owned(html)),
+            Uhtml { name, html } =>
+                Uhtml { name: owned(name), html: owned(html) },
+            UhtmlChange { name, html } =>
+                UhtmlChange { name: owned(name), html: owned(html) },
+            Popup(message) => Popup(owned(message)),
+            UpdateUser { user, named, avatar, settings } => UpdateUser {
+                user: owned(user),
+                named,
+                avatar: owned(avatar),
+                settings: owned(settings),
+            },
+            NameTaken { user, message } =>
+                NameTaken { user: owned(user), message: owned(message) },
+            QueryResponse { query_type, json } => QueryResponse {
+                query_type: owned(query_type),
+                json: owned(json),
+            },
+            Challstr(challstr) => Challstr(owned(challstr)),
+            Timestamp(timestamp) => Timestamp(timestamp),
+            Formats(formats) => Formats(owned(formats)),
+            UpdateChallenges(json) => UpdateChallenges(owned(json)),
+            UpdateSearch(json) => UpdateSearch(owned(json)),
+            Tournament { command, params } => Tournament {
+                command: owned(command),
+                params: params.into_iter().map(owned).collect(),
+            },
+            Battle { room, user1, user2 } => Battle {
+                room: owned(room),
+                user1: owned(user1),
+                user2: owned(user2),
+            },
+            Request(json) => Request(owned(json)),
+            Win(user) => Win(owned(user)),
+            Tie => Tie,
+            Message(message) => Message(owned(message)),
+            Unknown { command, params } => Unknown {
+                command: owned(command),
+                params: params.into_iter().map(owned).collect(),
+            },
+        }
+    }
+}
+
+fn owned(s: Cow<str>) -> Cow<'static, str> {
+    Cow::Owned(s.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::{ParseError, ServerMessage};
+
+    #[test]
+    fn parse_chat_test() {
+        assert_eq!(ServerMessage::parse("|c:|1500000000|+user|hi | there"),
+                   Ok(ServerMessage::ChatTimestamped {
+                       timestamp: 1500000000,
+                       user: "+user".into(),
+                       message: "hi | there".into(),
+                   }));
+        assert_eq!(ServerMessage::parse("|pm| Sender| Receiver|/invite room"),
+                   Ok(ServerMessage::Pm {
+                       sender: " Sender".into(),
+                       receiver: " Receiver".into(),
+                       message: "/invite room".into(),
+                   }));
+    }
+
+    #[test]
+    fn parse_misc_test() {
+        assert_eq!(ServerMessage::parse("|challstr|4|abc"),
+                   Ok(ServerMessage::Challstr("4|abc".into())));
+        assert_eq!(ServerMessage::parse("|users|2, user,@mod"),
+                   Ok(ServerMessage::Users(
+                       vec![" user".into(), "@mod".into()])));
+        assert_eq!(ServerMessage::parse("plain text"),
+                   Ok(ServerMessage::Message("plain text".into())));
+        assert_eq!(ServerMessage::parse("|tie"), Ok(ServerMessage::Tie));
+        assert_eq!(ServerMessage::parse("|something|a|b"),
+                   Ok(ServerMessage::Unknown {
+                       command: "something".into(),
+                       params: vec!["a".into(), "b".into()],
+                   }));
+    }
+
+    #[test]
+    fn borrowed_test() {
+        let line = String::from("|c|@mod|hello");
+        let message = ServerMessage::parse(&line).unwrap();
+        match message {
+            ServerMessage::Chat { message: Cow::Borrowed(m), .. } =>
+                assert_eq!(m, "hello"),
+            _ => panic!("message was not borrowed: {:?}", message),
+        }
+
+        let owned = message.into_owned();
+        drop(line);
+        assert_eq!(owned, ServerMessage::Chat {
+            user: "@mod".into(),
+            message: "hello".into(),
+        });
+    }
+
+    #[test]
+    fn parse_error_test() {
+        assert_eq!(ServerMessage::parse("|c:|"),
+                   Err(ParseError::InvalidParam {
+                       command: "c:".to_string(),
+                       index: 0,
+                       value: String::new(),
+                   }));
+        assert_eq!(ServerMessage::parse("|c:|123"),
+                   Err(ParseError::MissingParam {
+                       command: "c:".to_string(),
+                       index: 1,
+                   }));
+        assert!(ServerMessage::parse("|updateuser|name|2|1").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::Sender;
+
+use serde::{Deserialize, Deserializer};
+use serde_json::{self, Value};
+
+use command::Command;
+use helpers::sanitize;
+use rank::Rank;
+
+/// A question for the server, sent as `/cmd QUERYTYPE ARGS` and answered
+/// with `|queryresponse|QUERYTYPE|JSON`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// `/cmd userdetails USER`
+    UserDetails(String),
+    /// `/cmd roominfo ROOM`
+    RoomInfo(String),
+    /// `/cmd rooms`
+    Rooms,
+    /// `/cmd laddertop FORMAT`
+    LadderTop(String),
+    /// Any other query type, with its arguments.
+    Other(String, String),
+}
+
+impl Query {
+    /// Returns the query type the answer is sent with.
+    pub fn query_type(&self) -> &str {
+        match *self {
+            Query::UserDetails(_) => "userdetails",
+            Query::RoomInfo(_) => "roominfo",
+            Query::Rooms => "rooms",
+            Query::LadderTop(_) => "laddertop",
+            Query::Other(ref query_type, _) => query_type,
+        }
+    }
+
+    fn args(&self) -> &str {
+        match *self {
+            Query::UserDetails(ref args) |
+            Query::RoomInfo(ref args) |
+            Query::LadderTop(ref args) |
+            Query::Other(_, ref args) => args,
+            Query::Rooms => "",
+        }
+    }
+
+    pub fn to_command(&self) -> Command {
+        Command::Query(self.query_type().to_string(), self.args().to_string())
+    }
+}
+
+/// The answer to a `userdetails` query.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UserDetails {
+    pub id: String,
+    /// The name as the user styles it. Empty if the user is offline.
+    pub name: String,
+    /// The avatar number, or the name of a custom avatar.
+    pub avatar: String,
+    /// The global rank of the user.
+    pub group: Rank,
+    /// The rooms the user is in, by room id, with their rank in each.
+    /// Private rooms are only listed to staff.
+    pub rooms: BTreeMap<String, Rank>,
+    pub status: String,
+    pub away: bool,
+    pub autoconfirmed: bool,
+    pub online: bool,
+}
+
+#[derive(Deserialize)]
+struct RawUserDetails {
+    userid: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    avatar: Value,
+    #[serde(default)]
+    group: String,
+    /// An object keyed by rank symbol and room id, or `false` if the user is
+    /// offline.
+    #[serde(default)]
+    rooms: Value,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    autoconfirmed: bool,
+}
+
+impl<'de> Deserialize<'de> for UserDetails {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D)
+        -> Result<UserDetails, D::Error>
+    {
+        let raw = RawUserDetails::deserialize(deserializer)?;
+        let rooms = match raw.rooms {
+            Value::Object(ref rooms) => rooms.keys()
+                .map(|room| {
+                    let (rank, room, _) = Rank::split_user(room);
+                    (room.to_string(), rank)
+                })
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        let avatar = match raw.avatar {
+            Value::String(avatar) => avatar,
+            Value::Null => String::new(),
+            avatar => avatar.to_string(),
+        };
+        // Away statuses start with a !, like in user lists
+        let away = raw.status.starts_with('!');
+        let status = if away {
+            raw.status[1..].to_string()
+        } else {
+            raw.status
+        };
+
+        Ok(UserDetails {
+            online: raw.rooms.is_object(),
+            id: raw.userid,
+            name: raw.name,
+            avatar,
+            group: Rank::split_user(&raw.group).0,
+            rooms,
+            status,
+            away,
+            autoconfirmed: raw.autoconfirmed,
+        })
+    }
+}
+
+/// Where the answer to a query goes.
+pub enum Responder {
+    Channel(Sender<String>),
+    Callback(Box<FnMut(&str) + Send>),
+}
+
+struct Pending {
+    id: u64,
+    query_type: String,
+    /// The id of the user, room or format asked about, or empty.
+    key: String,
+    responder: Responder,
+}
+
+/// The queries waiting for an answer. Answers of one type come back in the
+/// order they were asked, but an answer naming the user, room or format it
+/// is about goes to the query that asked about it, so a query that never got
+/// an answer does not shift the others.
+#[derive(Default)]
+pub struct Queries {
+    next: u64,
+    pending: Vec<Pending>,
+}
+
+impl Queries {
+    pub fn new() -> Queries {
+        Queries::default()
+    }
+
+    /// Adds a query and returns its id.
+    pub fn register(&mut self, query: &Query, responder: Responder) -> u64 {
+        self.next += 1;
+        self.pending.push(Pending {
+            id: self.next,
+            query_type: query.query_type().to_string(),
+            key: sanitize(query.args()),
+            responder,
+        });
+        self.next
+    }
+
+    /// Forgets a query, e.g. once the caller stopped waiting for it.
+    pub fn cancel(&mut self, id: u64) {
+        self.pending.retain(|p| p.id != id);
+    }
+
+    /// Forgets every query. Their callers see the channel close.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Takes the query an answer belongs to, if any is waiting. Answers
+    /// about nobody in particular go to the oldest query of their type.
+    pub fn resolve(&mut self, query_type: &str, json: &str)
+        -> Option<Responder>
+    {
+        let key = answer_key(json);
+        let i = self.pending.iter()
+            .position(|p| p.query_type == query_type &&
+                      key.as_ref().map_or(true, |k| *k == p.key))?;
+        Some(self.pending.remove(i).responder)
+    }
+}
+
+impl fmt::Debug for Queries {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let types: Vec<&str> = self.pending.iter()
+            .map(|p| &p.query_type[..])
+            .collect();
+        f.debug_struct("Queries").field("pending", &types).finish()
+    }
+}
+
+/// Returns the id of what an answer is about: the `userid`, `id` or `roomid`
+/// of an object, or the first element of an array.
+fn answer_key(json: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(json).ok()?;
+    let key = match value {
+        Value::Object(ref o) => o.get("userid")
+            .or_else(|| o.get("id"))
+            .or_else(|| o.get("roomid"))
+            .and_then(|k| k.as_str()),
+        Value::Array(ref a) => a.first().and_then(|k| k.as_str()),
+        _ => None,
+    };
+    key.map(sanitize)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use serde_json;
+
+    use super::{Queries, Query, Responder, UserDetails};
+    use rank::Rank;
+
+    #[test]
+    fn resolve_test() {
+        let mut queries = Queries::new();
+        let (tx, rx) = mpsc::channel();
+        for user in &["Alice", "Bob", "Carol"] {
+            queries.register(&Query::UserDetails(user.to_string()),
+                             Responder::Channel(tx.clone()));
+        }
+        let rooms = queries.register(&Query::Rooms, Responder::Channel(tx));
+        queries.cancel(rooms);
+
+        // Answers go to the query about the same user, in any order
+        let bob = r#"{"id":"bob","userid":"bob","rooms":false}"#;
+        match queries.resolve("userdetails", bob) {
+            Some(Responder::Channel(tx)) => tx.send(bob.to_string()).unwrap(),
+            _ => panic!("no query for bob"),
+        }
+        assert_eq!(rx.recv().unwrap(), bob);
+        assert_eq!(queries.len(), 2);
+
+        // Answers about someone nobody asked about are left alone
+        let dave = r#"{"id":"dave","userid":"dave","rooms":false}"#;
+        assert!(queries.resolve("userdetails", dave).is_none());
+        assert_eq!(queries.len(), 2);
+
+        // Answers about nobody go to the oldest query of their type
+        assert!(queries.resolve("userdetails", "null").is_some());
+        assert!(queries.resolve("rooms", "{}").is_none());
+        assert_eq!(queries.len(), 1);
+        assert_eq!(Query::LadderTop("gen9ou".to_string()).to_command()
+                   .to_line(""), "|/cmd laddertop gen9ou");
+    }
+
+    #[test]
+    fn user_details_test() {
+        let details: UserDetails = serde_json::from_str(r#"{
+            "id": "alice", "userid": "alice", "name": "Alice",
+            "avatar": 294, "group": "+", "autoconfirmed": true,
+            "status": "!(Busy) brb",
+            "rooms": {"@techcode": {}, "lobby": {}, "*botdev": {}}
+        }"#).unwrap();
+        assert_eq!(details.name, "Alice");
+        assert_eq!(details.avatar, "294");
+        assert_eq!(details.group, Rank::Voice);
+        assert_eq!(details.rooms.get("techcode"), Some(&Rank::Moderator));
+        assert_eq!(details.rooms.get("lobby"), Some(&Rank::Regular));
+        assert_eq!(details.rooms.get("botdev"), Some(&Rank::Bot));
+        assert_eq!(details.status, "(Busy) brb");
+        assert!(details.away && details.online);
+
+        let details: UserDetails = serde_json::from_str(
+            r#"{"id": "bob", "userid": "bob", "rooms": false}"#).unwrap();
+        assert!(!details.online);
+        assert_eq!(details.group, Rank::Regular);
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+
+/// The rank of a user in a room, shown by the symbol in front of their name.
+/// Ranks are ordered from `Muted` up to `Administrator`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rank {
+    /// `!`
+    Muted,
+    /// `‽`
+    Locked,
+    /// No symbol, sent as a space.
+    Regular,
+    /// `^`
+    PrizeWinner,
+    /// `☆`
+    Player,
+    /// `+`
+    Voice,
+    /// `*`
+    Bot,
+    /// `%`
+    Driver,
+    /// `@`
+    Moderator,
+    /// `★`
+    Host,
+    /// `#`
+    RoomOwner,
+    /// `&`
+    Leader,
+    /// `~`
+    Administrator,
+}
+
+impl Rank {
+    /// Returns the rank for a symbol, or `None` if it is not a rank symbol.
+    pub fn from_symbol(c: char) -> Option<Rank> {
+        match c {
+            '!' => Some(Rank::Muted),
+            '‽' => Some(Rank::Locked),
+            ' ' => Some(Rank::Regular),
+            '^' => Some(Rank::PrizeWinner),
+            '☆' => Some(Rank::Player),
+            '+' => Some(Rank::Voice),
+            '*' => Some(Rank::Bot),
+            '%' => Some(Rank::Driver),
+            '@' => Some(Rank::Moderator),
+            '★' => Some(Rank::Host),
+            '#' => Some(Rank::RoomOwner),
+            '&' => Some(Rank::Leader),
+            '~' => Some(Rank::Administrator),
+            _ => None,
+        }
+    }
+
+    /// Returns the symbol shown in front of names with this rank.
+    pub fn symbol(&self) -> char {
+        match *self {
+            Rank::Muted => '!',
+            Rank::Locked => '‽',
+            Rank::Regular => ' ',
+            Rank::PrizeWinner => '^',
+            Rank::Player => '☆',
+            Rank::Voice => '+',
+            Rank::Bot => '*',
+            Rank::Driver => '%',
+            Rank::Moderator => '@',
+            Rank::Host => '★',
+            Rank::RoomOwner => '#',
+            Rank::Leader => '&',
+            Rank::Administrator => '~',
+        }
+    }
+
+    /// Returns true if this rank is `rank` or higher.
+    pub fn at_least(&self, rank: Rank) -> bool {
+        *self >= rank
+    }
+
+    /// Splits a user as sent by the server, e.g. `@Name@!`, into their rank,
+    /// name, and whether they are away. A name without a rank symbol in
+    /// front is `Regular`.
+    pub fn split_user(user: &str) -> (Rank, &str, bool) {
+        let (rank, rest) = match user.chars().next().and_then(Rank::from_symbol) {
+            Some(rank) => (rank, &user[rank.symbol().len_utf8()..]),
+            None => (Rank::Regular, user),
+        };
+
+        // Statuses follow an @, which names cannot contain
+        match rest.find('@') {
+            Some(i) => (rank, &rest[..i], rest[i + 1..].starts_with('!')),
+            None => (rank, rest, false),
+        }
+    }
+}
+
+impl Default for Rank {
+    fn default() -> Rank {
+        Rank::Regular
+    }
+}
+
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rank;
+
+    #[test]
+    fn ordering_test() {
+        assert!(Rank::Administrator > Rank::Leader);
+        assert!(Rank::Moderator.at_least(Rank::Driver));
+        assert!(Rank::Driver.at_least(Rank::Driver));
+        assert!(!Rank::Voice.at_least(Rank::Driver));
+        assert!(Rank::Regular > Rank::Locked);
+        assert!(Rank::Locked > Rank::Muted);
+    }
+
+    #[test]
+    fn split_user_test() {
+        assert_eq!(Rank::split_user("@Name@!"), (Rank::Moderator, "Name", true));
+        assert_eq!(Rank::split_user(" Name"), (Rank::Regular, "Name", false));
+        assert_eq!(Rank::split_user("★Host"), (Rank::Host, "Host", false));
+        assert_eq!(Rank::split_user("Name"), (Rank::Regular, "Name", false));
+        assert_eq!(Rank::split_user("+Voice@busy"), (Rank::Voice, "Voice", false));
+        assert_eq!(Rank::split_user(""), (Rank::Regular, "", false));
+    }
+}
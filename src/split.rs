@@ -0,0 +1,144 @@
+/// Splits `text` into messages no longer than `limit`, breaking at
+/// whitespace where possible and never inside a character. Length is
+/// counted in UTF-16 code units, the way Showdown counts it.
+///
+/// If `max_parts` is not 0, at most that many messages are returned and the
+/// last one ends in `…` when text was cut off. If `numbered` is true, every
+/// message ends in a ` (n/m)` suffix, which counts towards the limit.
+pub fn split_message(text: &str, limit: usize, max_parts: usize,
+                     numbered: bool) -> Vec<String> {
+    let text = text.trim();
+    if width(text) <= limit {
+        return vec![text.to_string()];
+    }
+
+    let mut total = 1;
+    loop {
+        let reserve = if numbered { width(&suffix(total, total)) } else { 0 };
+        let part_limit = if limit > reserve { limit - reserve } else { 1 };
+
+        let mut parts = chunks(text, part_limit);
+        if max_parts > 0 && parts.len() > max_parts {
+            parts.truncate(max_parts);
+            let last = parts.pop().unwrap();
+            parts.push(ellipsize(&last, part_limit));
+        }
+
+        let count = parts.len();
+        if !numbered {
+            return parts;
+        }
+        if suffix(count, count).len() <= suffix(total, total).len() {
+            return parts.into_iter()
+                .enumerate()
+                .map(|(i, p)| p + &suffix(i + 1, count))
+                .collect();
+        }
+        total = count;
+    }
+}
+
+fn width(s: &str) -> usize {
+    s.chars().map(|c| c.len_utf16()).sum()
+}
+
+fn suffix(n: usize, total: usize) -> String {
+    format!(" ({}/{})", n, total)
+}
+
+/// Greedily cuts `text` into pieces of at most `limit`.
+fn chunks(text: &str, limit: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        if width(rest) <= limit {
+            parts.push(rest.to_string());
+            break;
+        }
+
+        // Find the longest prefix that fits, but always take at least one
+        // character so that we make progress
+        let mut end = 0;
+        let mut w = 0;
+        for (i, c) in rest.char_indices() {
+            w += c.len_utf16();
+            if w > limit && i > 0 {
+                break;
+            }
+            end = i + c.len_utf8();
+        }
+
+        // Prefer breaking at the last whitespace that fits
+        let at_boundary = rest[end..].starts_with(char::is_whitespace);
+        let cut = match rest[..end].rfind(char::is_whitespace) {
+            Some(i) if i > 0 && !at_boundary => i,
+            _ => end,
+        };
+
+        parts.push(rest[..cut].trim_right().to_string());
+        rest = rest[cut..].trim_left();
+    }
+
+    parts
+}
+
+/// Shortens `text` to make room for a trailing `…` within `limit`.
+fn ellipsize(text: &str, limit: usize) -> String {
+    let mut s = text.to_string();
+    while !s.is_empty() && width(&s) + 1 > limit {
+        s.pop();
+    }
+
+    // Avoid ending on half a word if there is whitespace to break at
+    if s.len() < text.len() {
+        if let Some(i) = s.rfind(char::is_whitespace) {
+            s.truncate(i);
+        }
+    }
+    let mut s = s.trim_right().to_string();
+    s.push('…');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_message, width};
+
+    #[test]
+    fn short_message_test() {
+        assert_eq!(split_message("hello", 300, 0, false), vec!["hello"]);
+    }
+
+    #[test]
+    fn word_boundary_test() {
+        assert_eq!(split_message("aaa bbb ccc", 7, 0, false),
+                   vec!["aaa bbb", "ccc"]);
+        assert_eq!(split_message("abcdefghij", 4, 0, false),
+                   vec!["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn multibyte_test() {
+        let text = "日本語のテキスト".repeat(50);
+        let parts = split_message(&text, 299, 0, false);
+        assert_eq!(parts.concat(), text);
+        assert!(parts.iter().all(|p| width(p) <= 299));
+
+        // Emoji take two UTF-16 code units each
+        let parts = split_message(&"😀".repeat(5), 4, 0, false);
+        assert_eq!(parts, vec!["😀😀", "😀😀", "😀"]);
+    }
+
+    #[test]
+    fn numbered_test() {
+        let parts = split_message("aaa bbb ccc ddd", 13, 0, true);
+        assert_eq!(parts, vec!["aaa bbb (1/2)", "ccc ddd (2/2)"]);
+    }
+
+    #[test]
+    fn max_parts_test() {
+        let parts = split_message("aaa bbb ccc ddd eee", 7, 2, false);
+        assert_eq!(parts, vec!["aaa bbb", "ccc…"]);
+    }
+}
@@ -1,12 +1,40 @@
 use std::collections::{BTreeSet, HashMap};
 use std::sync::{Arc, Mutex};
 
-use helpers::sanitize;
+use command::Command;
+use helpers::{escape, sanitize, split_message};
+use outbox::Priority;
+use query::UserDetails;
+use rank::Rank;
 
 /// A `Target` for the bot to reply to.
+///
+/// Text sent with `send` may come from users, so it is escaped and can never
+/// run a chat command. Commands go through `send_command`.
 pub trait Target: Sync + Clone {
     fn new(name: &str) -> Self;
-    fn send(&self, bot: &Arc<Mutex<::Bot>>, text: &str);
+    fn send_with_priority(&self, bot: &Arc<Mutex<::Bot>>, text: &str,
+                          priority: Priority);
+    fn send_command(&self, bot: &Arc<Mutex<::Bot>>, command: &Command);
+
+    /// Sends a message to the `Target` as a reply.
+    fn send(&self, bot: &Arc<Mutex<::Bot>>, text: &str) {
+        self.send_with_priority(bot, text, Priority::Reply);
+    }
+}
+
+/// Splits text into escaped messages that fit Showdown's length limit,
+/// following the bot's config. One character is kept free for escaping.
+fn split(bot: &Arc<Mutex<::Bot>>, text: &str) -> Vec<String> {
+    let b = bot.lock().unwrap();
+    let limit = b.config.max_message_length.saturating_sub(1);
+    split_message(text,
+                  limit,
+                  b.config.max_message_parts,
+                  b.config.number_message_parts)
+        .iter()
+        .map(|part| escape(part))
+        .collect()
 }
 
 /// A `Room` implements `Target`. If the bot replies to a chat message from
@@ -28,14 +56,18 @@ impl Target for Room {
         }
     }
 
-    fn send(&self, bot: &Arc<Mutex<::Bot>>, text: &str) {
-        let to_send = format!("{}|{}", self.name, text);
-        if to_send.len() > 300 {
-            bot.lock().unwrap().send(&to_send[..299])
-        } else {
-            bot.lock().unwrap().send(to_send)
+    fn send_with_priority(&self, bot: &Arc<Mutex<::Bot>>, text: &str,
+                          priority: Priority) {
+        for part in split(bot, text) {
+            bot.lock().unwrap().send_with_priority(
+                format!("{}|{}", self.name, part), priority);
         }
     }
+
+    /// Sends a trusted command from the room.
+    fn send_command(&self, bot: &Arc<Mutex<::Bot>>, command: &Command) {
+        bot.lock().unwrap().send_command(command, &self.name);
+    }
 }
 
 impl Room {
@@ -55,14 +87,17 @@ impl Room {
     }
 }
 
-/// A `User`. Contains their name and known authorization levels.
+/// A `User`. Contains their name, their global rank and known rank in each
+/// room, and whether they are away.
 ///
 /// A `User` is uniquely identified by `sanitize`ing its `name`.
 #[derive(Debug, Clone)]
 pub struct User {
     pub id: String,
     pub name: String,
-    auths: Arc<Mutex<HashMap<String, String>>>,
+    ranks: Arc<Mutex<HashMap<String, Rank>>>,
+    group: Arc<Mutex<Rank>>,
+    away: Arc<Mutex<bool>>,
 }
 
 impl Target for User {
@@ -71,40 +106,70 @@ impl Target for User {
         User {
             id: sanitize(name),
             name: String::from(name),
-            auths: Arc::new(Mutex::new(HashMap::new())),
+            ranks: Arc::new(Mutex::new(HashMap::new())),
+            group: Arc::new(Mutex::new(Rank::Regular)),
+            away: Arc::new(Mutex::new(false)),
         }
     }
 
     /// Sends a private message to a `User`.
-    fn send(&self, bot: &Arc<Mutex<::Bot>>, text: &str) {
-        let to_send = format!("|/w {},{}", self.name, text);
-        if to_send.len() > 300 {
-            bot.lock().unwrap().send(&to_send[..299])
-        } else {
-            bot.lock().unwrap().send(to_send)
+    fn send_with_priority(&self, bot: &Arc<Mutex<::Bot>>, text: &str,
+                          priority: Priority) {
+        for part in split(bot, text) {
+            bot.lock().unwrap().send_with_priority(
+                Command::Whisper(self.name.clone(), part).to_line(""),
+                priority);
         }
     }
+
+    /// Sends a trusted command in the private chat with the `User`.
+    fn send_command(&self, bot: &Arc<Mutex<::Bot>>, command: &Command) {
+        bot.lock().unwrap().send_with_priority(
+            Command::Whisper(self.name.clone(), command.to_string())
+                .to_line(""),
+            command.priority());
+    }
 }
 
 impl User {
-    /// Adds an authorization level in a room.
-    fn add_auth(&mut self, auth: &str, room: &str) {
-        self.auths.lock().unwrap()
-            .entry(sanitize(room))
-            .or_insert(String::from(auth));
-    }
-
-    fn has_auth(&self, auth: &str, room: &str) -> bool {
-        match self.auths.lock().unwrap().get(room) {
-            Some(v) => {
-                if v == auth {
-                    true
-                } else {
-                    false
-                }
-            },
-            None => false
-        }
+    /// Sets the rank of the `User` in a room.
+    fn set_rank(&mut self, rank: Rank, room: &str) {
+        self.ranks.lock().unwrap().insert(sanitize(room), rank);
+    }
+
+    /// Returns the rank of the `User` in a room, or `Regular` if it is not
+    /// known.
+    pub fn rank(&self, room: &str) -> Rank {
+        self.ranks.lock().unwrap()
+            .get(&sanitize(room))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the global rank of the `User`, as far as it is known.
+    pub fn group(&self) -> Rank {
+        *self.group.lock().unwrap()
+    }
+
+    /// Returns the highest rank the `User` is known to have in any room,
+    /// counting their global rank.
+    pub fn highest_rank(&self) -> Rank {
+        self.ranks.lock().unwrap()
+            .values()
+            .cloned()
+            .max()
+            .unwrap_or_default()
+            .max(self.group())
+    }
+
+    /// Returns true if the `User` has at least `rank` in a room.
+    pub fn has_rank(&self, rank: Rank, room: &str) -> bool {
+        self.rank(room).at_least(rank)
+    }
+
+    /// Returns true if the `User` was last seen away or busy.
+    pub fn is_away(&self) -> bool {
+        *self.away.lock().unwrap()
     }
 }
 
@@ -121,7 +186,7 @@ impl<T: Target> CacheMap<T> {
     pub fn insert(&mut self, name: &str) {
         self.map.lock().unwrap()
             .entry(sanitize(name))
-            .or_insert(Target::new(name));
+            .or_insert_with(|| Target::new(name));
     }
 
     pub fn remove(&mut self, name: &str) {
@@ -138,14 +203,14 @@ impl CacheMap<Room> {
     pub fn insert_user_in_room(&mut self, u: &str, r: &str) -> bool {
         let mut map = self.map.lock().unwrap();
         map.entry(sanitize(r))
-            .or_insert(Target::new(r))
+            .or_insert_with(|| Target::new(r))
             .insert_user(u)
     }
 
     pub fn remove_user_from_room(&mut self, u: &str, r: &str) -> bool {
         let mut map = self.map.lock().unwrap();
         map.entry(sanitize(r))
-            .or_insert(Target::new(r))
+            .or_insert_with(|| Target::new(r))
             .remove_user(u)
     }
 
@@ -158,17 +223,57 @@ impl CacheMap<Room> {
 }
 
 impl CacheMap<User> {
-    pub fn add_auth_to_user_in_room(&mut self, a: &str, u: &str, r: &str) {
+    pub fn set_rank_of_user_in_room(&mut self, rank: Rank, u: &str, r: &str) {
         let mut map = self.map.lock().unwrap();
         map.entry(sanitize(u))
-            .or_insert(Target::new(u))
-            .add_auth(a, r);
+            .or_insert_with(|| Target::new(u))
+            .set_rank(rank, r);
+    }
+
+    pub fn rank_of_user_in_room(&self, u: &str, r: &str) -> Rank {
+        match self.map.lock().unwrap().get(&sanitize(u)) {
+            Some(user) => user.rank(r),
+            None => Rank::Regular
+        }
+    }
+
+    pub fn highest_rank_of_user(&self, u: &str) -> Rank {
+        match self.map.lock().unwrap().get(&sanitize(u)) {
+            Some(user) => user.highest_rank(),
+            None => Rank::Regular
+        }
+    }
+
+    pub fn set_user_away(&mut self, u: &str, away: bool) {
+        let mut map = self.map.lock().unwrap();
+        *map.entry(sanitize(u))
+            .or_insert_with(|| Target::new(u))
+            .away.lock().unwrap() = away;
+    }
+
+    /// Updates a user with the answer to a `userdetails` query.
+    pub fn update_details(&self, details: &UserDetails) {
+        let name = if details.name.is_empty() {
+            &details.id
+        } else {
+            &details.name
+        };
+        let mut map = self.map.lock().unwrap();
+        let user = map.entry(details.id.clone())
+            .or_insert_with(|| Target::new(name));
+        *user.group.lock().unwrap() = details.group;
+        *user.away.lock().unwrap() = details.away;
+        for (room, &rank) in &details.rooms {
+            user.set_rank(rank, room);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use ::bot::Bot;
+    use ::query::UserDetails;
+    use ::rank::Rank;
 
     static TEST_PATH: &'static str = "examples/example_config.toml";
 
@@ -190,4 +295,36 @@ mod tests {
         b.room_map.remove_user_from_room("testuser", "testroom");
         assert!(!b.room_map.contains_user_in_room("testuser", "testroom"));
     }
+
+    #[test]
+    fn user_rank_test() {
+        let mut b = Bot::new(TEST_PATH).unwrap();
+        assert_eq!(b.user_map.rank_of_user_in_room("testuser", "testroom"),
+                   Rank::Regular);
+        b.user_map.set_rank_of_user_in_room(Rank::Voice, "testuser", "testroom");
+        b.user_map.set_rank_of_user_in_room(Rank::Driver, "Test User", "Test Room");
+        assert_eq!(b.user_map.rank_of_user_in_room("testuser", "testroom"),
+                   Rank::Driver);
+        assert_eq!(b.user_map.rank_of_user_in_room("testuser", "lobby"),
+                   Rank::Regular);
+    }
+
+    #[test]
+    fn update_details_test() {
+        let b = Bot::new(TEST_PATH).unwrap();
+        let mut details = UserDetails::default();
+        details.id = "testuser".to_string();
+        details.group = Rank::Voice;
+        details.rooms.insert("testroom".to_string(), Rank::Driver);
+        b.user_map.update_details(&details);
+        assert_eq!(b.user_map.rank_of_user_in_room("testuser", "testroom"),
+                   Rank::Driver);
+        assert_eq!(b.user_map.highest_rank_of_user("testuser"), Rank::Driver);
+
+        details.rooms.clear();
+        details.group = Rank::Administrator;
+        b.user_map.update_details(&details);
+        assert_eq!(b.user_map.highest_rank_of_user("testuser"),
+                   Rank::Administrator);
+    }
 }
@@ -0,0 +1,649 @@
+use std::iter;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+use serde_json;
+
+use battle::{Details, PokemonRequest};
+
+/// The names of the stats in exports, in the order the packed format uses.
+const STAT_NAMES: [&str; 6] = ["HP", "Atk", "Def", "SpA", "SpD", "Spe"];
+
+/// A value for each stat, used for EVs and IVs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    #[serde(default)]
+    pub hp: u32,
+    #[serde(default)]
+    pub atk: u32,
+    #[serde(default)]
+    pub def: u32,
+    #[serde(default)]
+    pub spa: u32,
+    #[serde(default)]
+    pub spd: u32,
+    #[serde(default)]
+    pub spe: u32,
+}
+
+impl Stats {
+    /// Returns stats that are all `value`.
+    pub fn all(value: u32) -> Stats {
+        Stats::from_array([value; 6])
+    }
+
+    fn from_array(s: [u32; 6]) -> Stats {
+        Stats {
+            hp: s[0], atk: s[1], def: s[2], spa: s[3], spd: s[4], spe: s[5],
+        }
+    }
+
+    fn to_array(self) -> [u32; 6] {
+        [self.hp, self.atk, self.def, self.spa, self.spd, self.spe]
+    }
+
+    /// Formats the stats that differ from `default` as exports do, e.g.
+    /// `252 Atk / 4 SpD / 252 Spe`.
+    fn to_export(self, default: u32) -> String {
+        self.to_array().iter()
+            .zip(STAT_NAMES.iter())
+            .filter(|&(&value, _)| value != default)
+            .map(|(value, name)| format!("{} {}", value, name))
+            .collect::<Vec<String>>()
+            .join(" / ")
+    }
+
+    fn from_export(s: &str, default: u32) -> ::Result<Stats> {
+        let mut stats = [default; 6];
+        for part in s.split('/') {
+            let mut words = part.split_whitespace();
+            let (value, name) = match (words.next(), words.next()) {
+                (Some(value), Some(name)) => (value, name),
+                _ => return Err(invalid(part)),
+            };
+            let i = STAT_NAMES.iter()
+                .position(|n| n.eq_ignore_ascii_case(name))
+                .ok_or_else(|| invalid(part))?;
+            stats[i] = number(value)?;
+        }
+        Ok(Stats::from_array(stats))
+    }
+
+    /// Packs the stats as comma separated values, leaving out the ones that
+    /// are `default`, or everything if they all are.
+    fn pack(self, default: u32) -> String {
+        let stats = self.to_array();
+        if stats.iter().all(|&value| value == default) {
+            return String::new();
+        }
+        stats.iter()
+            .map(|&value| if value == default {
+                String::new()
+            } else {
+                value.to_string()
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    fn unpack(s: &str, default: u32) -> ::Result<Stats> {
+        let mut stats = [default; 6];
+        for (stat, value) in stats.iter_mut().zip(s.split(',')) {
+            if !value.is_empty() {
+                *stat = number(value)?;
+            }
+        }
+        Ok(Stats::from_array(stats))
+    }
+}
+
+/// A Pokemon as it is built for a team. The JSON form uses the field names
+/// of Showdown's own sets.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PokemonSet {
+    /// The nickname, if it differs from the species.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub species: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ability: Option<String>,
+    #[serde(default)]
+    pub moves: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nature: Option<String>,
+    /// `M` or `F`, or `None` for a random or no gender.
+    #[serde(default, deserialize_with = "gender",
+            skip_serializing_if = "Option::is_none")]
+    pub gender: Option<char>,
+    #[serde(default = "default_evs")]
+    pub evs: Stats,
+    #[serde(default = "default_ivs")]
+    pub ivs: Stats,
+    #[serde(default = "default_level")]
+    pub level: u32,
+    #[serde(default)]
+    pub shiny: bool,
+    #[serde(default = "default_happiness")]
+    pub happiness: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pokeball: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hp_type: Option<String>,
+    #[serde(default = "default_dynamax_level")]
+    pub dynamax_level: u32,
+    #[serde(default)]
+    pub gigantamax: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tera_type: Option<String>,
+}
+
+impl PokemonSet {
+    /// Creates a set of a species with nothing else chosen.
+    pub fn new(species: &str) -> PokemonSet {
+        PokemonSet {
+            name: None,
+            species: species.to_string(),
+            item: None,
+            ability: None,
+            moves: Vec::new(),
+            nature: None,
+            gender: None,
+            evs: default_evs(),
+            ivs: default_ivs(),
+            level: default_level(),
+            shiny: false,
+            happiness: default_happiness(),
+            pokeball: None,
+            hp_type: None,
+            dynamax_level: default_dynamax_level(),
+            gigantamax: false,
+            tera_type: None,
+        }
+    }
+
+    /// Reads what a battle request shows of one of the bot's Pokemon. The
+    /// item, ability and moves are ids, and EVs, IVs and nature are unknown.
+    pub fn from_request(pokemon: &PokemonRequest) -> Option<PokemonSet> {
+        let details = Details::parse(&pokemon.details)?;
+        let mut set = PokemonSet::new(&details.species);
+        set.name = pokemon.id().map(|id| id.name);
+        set.item = Some(pokemon.item.clone());
+        set.ability = Some(pokemon.base_ability.clone());
+        set.moves = pokemon.moves.clone();
+        set.gender = details.gender;
+        set.level = details.level;
+        set.shiny = details.shiny;
+        set.tera_type = Some(pokemon.tera_type.clone());
+        set.normalize();
+        Some(set)
+    }
+
+    /// Parses one set in the export format, e.g.
+    ///
+    /// ```text
+    /// Sparky (Pikachu) (F) @ Light Ball
+    /// Ability: Static
+    /// EVs: 252 SpA / 4 SpD / 252 Spe
+    /// Timid Nature
+    /// - Thunderbolt
+    /// ```
+    pub fn from_export(text: &str) -> ::Result<PokemonSet> {
+        let lines: Vec<&str> = text.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect();
+        PokemonSet::from_lines(&lines)
+    }
+
+    fn from_lines(lines: &[&str]) -> ::Result<PokemonSet> {
+        let first = match lines.first() {
+            Some(first) => *first,
+            None => return Err(invalid("")),
+        };
+
+        let (mut first, item) = match first.rfind(" @ ") {
+            Some(i) => (first[..i].trim(), Some(first[i + 3..].trim())),
+            None => (first, None),
+        };
+        let mut gender = None;
+        if first.ends_with(" (M)") || first.ends_with(" (F)") {
+            gender = first.chars().rev().nth(1);
+            first = &first[..first.len() - 4];
+        }
+        let (name, species) = match first.rfind(" (") {
+            Some(i) if first.ends_with(')') =>
+                (Some(&first[..i]), &first[i + 2..first.len() - 1]),
+            _ => (None, first),
+        };
+
+        let mut set = PokemonSet::new(species);
+        set.name = name.map(str::to_string);
+        set.item = item.map(str::to_string);
+        set.gender = gender;
+
+        for line in &lines[1..] {
+            if line.starts_with("- ") || line.starts_with("~ ") {
+                let name = line[2..].trim();
+                let name = if name.starts_with("Hidden Power [") &&
+                    name.ends_with(']') {
+                    format!("Hidden Power {}", &name[14..name.len() - 1])
+                } else {
+                    name.to_string()
+                };
+                set.moves.push(name);
+            } else if let Some(v) = value(line, "Ability:")
+                .or_else(|| value(line, "Trait:")) {
+                set.ability = Some(v.to_string());
+            } else if let Some(v) = value(line, "Level:") {
+                set.level = number(v)?;
+            } else if let Some(v) = value(line, "Shiny:") {
+                set.shiny = v == "Yes";
+            } else if let Some(v) = value(line, "Happiness:") {
+                set.happiness = number(v)?;
+            } else if let Some(v) = value(line, "Pokeball:") {
+                set.pokeball = Some(v.to_string());
+            } else if let Some(v) = value(line, "Hidden Power:") {
+                set.hp_type = Some(v.to_string());
+            } else if let Some(v) = value(line, "Dynamax Level:") {
+                set.dynamax_level = number(v)?;
+            } else if let Some(v) = value(line, "Gigantamax:") {
+                set.gigantamax = v == "Yes";
+            } else if let Some(v) = value(line, "Tera Type:") {
+                set.tera_type = Some(v.to_string());
+            } else if let Some(v) = value(line, "EVs:") {
+                set.evs = Stats::from_export(v, 0)?;
+            } else if let Some(v) = value(line, "IVs:") {
+                set.ivs = Stats::from_export(v, 31)?;
+            } else if line.ends_with(" Nature") {
+                set.nature = Some(line[..line.len() - 7].trim().to_string());
+            }
+        }
+
+        set.normalize();
+        Ok(set)
+    }
+
+    /// Formats the set in the export format, without the trailing spaces
+    /// Showdown adds to each line.
+    pub fn to_export(&self) -> String {
+        let mut first = match self.name {
+            Some(ref name) => format!("{} ({})", name, self.species),
+            None => self.species.clone(),
+        };
+        if let Some(gender) = self.gender {
+            first.push_str(&format!(" ({})", gender));
+        }
+        if let Some(ref item) = self.item {
+            first.push_str(&format!(" @ {}", item));
+        }
+
+        let mut lines = vec![first];
+        if let Some(ref ability) = self.ability {
+            lines.push(format!("Ability: {}", ability));
+        }
+        if self.level != default_level() {
+            lines.push(format!("Level: {}", self.level));
+        }
+        if self.shiny {
+            lines.push("Shiny: Yes".to_string());
+        }
+        if self.happiness != default_happiness() {
+            lines.push(format!("Happiness: {}", self.happiness));
+        }
+        if let Some(ref pokeball) = self.pokeball {
+            lines.push(format!("Pokeball: {}", pokeball));
+        }
+        if let Some(ref hp_type) = self.hp_type {
+            lines.push(format!("Hidden Power: {}", hp_type));
+        }
+        if self.dynamax_level != default_dynamax_level() {
+            lines.push(format!("Dynamax Level: {}", self.dynamax_level));
+        }
+        if self.gigantamax {
+            lines.push("Gigantamax: Yes".to_string());
+        }
+        if let Some(ref tera_type) = self.tera_type {
+            lines.push(format!("Tera Type: {}", tera_type));
+        }
+        let evs = self.evs.to_export(0);
+        if !evs.is_empty() {
+            lines.push(format!("EVs: {}", evs));
+        }
+        if let Some(ref nature) = self.nature {
+            lines.push(format!("{} Nature", nature));
+        }
+        let ivs = self.ivs.to_export(31);
+        if !ivs.is_empty() {
+            lines.push(format!("IVs: {}", ivs));
+        }
+        for name in &self.moves {
+            if name.starts_with("Hidden Power ") {
+                lines.push(format!("- Hidden Power [{}]", &name[13..]));
+            } else {
+                lines.push(format!("- {}", name));
+            }
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    /// Packs the set the way `/utm` expects. Like Showdown, names other than
+    /// the nickname, nature and types are packed without spaces or
+    /// punctuation.
+    pub fn pack(&self) -> String {
+        let name = self.name.as_ref().unwrap_or(&self.species);
+        let species = pack_name(&self.species);
+        let mut fields = vec![
+            name.clone(),
+            if pack_name(name) == species { String::new() } else { species },
+            self.item.as_ref().map(|i| pack_name(i)).unwrap_or_default(),
+            self.ability.as_ref().map(|a| pack_name(a)).unwrap_or_default(),
+            self.moves.iter()
+                .map(|m| pack_name(m))
+                .collect::<Vec<String>>()
+                .join(","),
+            self.nature.clone().unwrap_or_default(),
+            self.evs.pack(0),
+            self.gender.map(|g| g.to_string()).unwrap_or_default(),
+            self.ivs.pack(31),
+            if self.shiny { "S".to_string() } else { String::new() },
+            if self.level == default_level() {
+                String::new()
+            } else {
+                self.level.to_string()
+            },
+        ];
+
+        let mut last = if self.happiness == default_happiness() {
+            String::new()
+        } else {
+            self.happiness.to_string()
+        };
+        if self.pokeball.is_some() || self.hp_type.is_some() ||
+            self.gigantamax || self.tera_type.is_some() ||
+            self.dynamax_level != default_dynamax_level() {
+            let misc = [
+                self.hp_type.clone().unwrap_or_default(),
+                self.pokeball.as_ref()
+                    .map(|p| pack_name(p))
+                    .unwrap_or_default(),
+                if self.gigantamax { "G".to_string() } else { String::new() },
+                if self.dynamax_level == default_dynamax_level() {
+                    String::new()
+                } else {
+                    self.dynamax_level.to_string()
+                },
+                self.tera_type.clone().unwrap_or_default(),
+            ];
+            last.push(',');
+            last.push_str(&misc.join(","));
+        }
+        fields.push(last);
+
+        fields.join("|")
+    }
+
+    /// Unpacks a set from the packed format. Names come back with spaces
+    /// before capital letters and numbers, e.g. `ChoiceScarf` becomes
+    /// `Choice Scarf`; other punctuation is lost, which Showdown ignores
+    /// when it reads a team.
+    pub fn unpack(packed: &str) -> ::Result<PokemonSet> {
+        let fields: Vec<&str> = packed.split('|').collect();
+        let field = |i: usize| fields.get(i).cloned().unwrap_or("");
+        if field(0).is_empty() {
+            return Err(invalid(packed));
+        }
+
+        let mut set = if field(1).is_empty() {
+            PokemonSet::new(field(0))
+        } else {
+            let mut set = PokemonSet::new(&unpack_name(field(1)));
+            set.name = Some(field(0).to_string());
+            set
+        };
+        set.item = Some(unpack_name(field(2)));
+        set.ability = Some(unpack_name(field(3)));
+        set.moves = field(4).split(',')
+            .filter(|m| !m.is_empty())
+            .map(unpack_name)
+            .collect();
+        set.nature = Some(field(5).to_string());
+        set.evs = Stats::unpack(field(6), 0)?;
+        set.gender = field(7).chars().next();
+        set.ivs = Stats::unpack(field(8), 31)?;
+        set.shiny = field(9) == "S";
+        if !field(10).is_empty() {
+            set.level = number(field(10))?;
+        }
+
+        // Happiness is followed by the fields added in later generations
+        let extra: Vec<&str> = field(11).split(',').collect();
+        let misc = |i: usize| extra.get(i).cloned().unwrap_or("");
+        if !misc(0).is_empty() {
+            set.happiness = number(misc(0))?;
+        }
+        set.hp_type = Some(misc(1).to_string());
+        set.pokeball = Some(unpack_name(misc(2)));
+        set.gigantamax = !misc(3).is_empty();
+        if !misc(4).is_empty() {
+            set.dynamax_level = number(misc(4))?;
+        }
+        set.tera_type = Some(misc(5).to_string());
+
+        set.normalize();
+        Ok(set)
+    }
+
+    /// Drops empty names and a nickname that is just the species.
+    fn normalize(&mut self) {
+        for field in &mut [&mut self.item, &mut self.ability, &mut self.nature,
+                           &mut self.pokeball, &mut self.hp_type,
+                           &mut self.tera_type, &mut self.name] {
+            let empty = match **field {
+                Some(ref f) => f.is_empty(),
+                None => false,
+            };
+            if empty {
+                **field = None;
+            }
+        }
+        if self.name.as_ref() == Some(&self.species) {
+            self.name = None;
+        }
+    }
+}
+
+/// A team of `PokemonSet`s, which converts between the export, packed and
+/// JSON formats.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Team {
+    pub pokemon: Vec<PokemonSet>,
+}
+
+impl Team {
+    /// Parses a team in the export format, with sets separated by blank
+    /// lines. Headers like `=== [gen9ou] Team ===` are skipped.
+    pub fn from_export(text: &str) -> ::Result<Team> {
+        let mut pokemon = Vec::new();
+        let mut lines = Vec::new();
+        for line in text.lines().map(str::trim).chain(iter::once("")) {
+            if line.starts_with("===") {
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(line);
+            } else if !lines.is_empty() {
+                pokemon.push(PokemonSet::from_lines(&lines)?);
+                lines.clear();
+            }
+        }
+        Ok(Team { pokemon })
+    }
+
+    pub fn to_export(&self) -> String {
+        self.pokemon.iter()
+            .map(|p| p.to_export())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Unpacks a team from the packed format, with sets separated by `]`.
+    pub fn from_packed(packed: &str) -> ::Result<Team> {
+        let pokemon = packed.split(']')
+            .filter(|p| !p.is_empty())
+            .map(PokemonSet::unpack)
+            .collect::<::Result<Vec<PokemonSet>>>()?;
+        Ok(Team { pokemon })
+    }
+
+    pub fn to_packed(&self) -> String {
+        self.pokemon.iter()
+            .map(|p| p.pack())
+            .collect::<Vec<String>>()
+            .join("]")
+    }
+
+    /// Parses a team from a JSON array of sets.
+    pub fn from_json(json: &str) -> ::Result<Team> {
+        let mut pokemon: Vec<PokemonSet> = serde_json::from_str(json)?;
+        for set in &mut pokemon {
+            set.normalize();
+        }
+        Ok(Team { pokemon })
+    }
+
+    pub fn to_json(&self) -> ::Result<String> {
+        Ok(serde_json::to_string(&self.pokemon)?)
+    }
+}
+
+/// Returns the value of a `Key: value` line.
+fn value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    if line.starts_with(key) {
+        Some(line[key.len()..].trim())
+    } else {
+        None
+    }
+}
+
+/// Strips everything but letters and digits from a name.
+fn pack_name(name: &str) -> String {
+    name.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
+}
+
+/// Puts spaces back into a packed name, before capital letters and around
+/// numbers.
+fn unpack_name(name: &str) -> String {
+    let mut unpacked = String::new();
+    let mut digits = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() || c.is_ascii_digit() != digits {
+            unpacked.push(' ');
+        }
+        digits = c.is_ascii_digit();
+        unpacked.push(c);
+    }
+    unpacked.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn number<T: FromStr>(s: &str) -> ::Result<T> {
+    s.trim().parse().map_err(|_| invalid(s))
+}
+
+fn invalid(s: &str) -> ::Error {
+    ::Error::InvalidTeam(s.trim().to_string())
+}
+
+/// Reads a gender, which Showdown sends as an empty string if there is none.
+fn gender<'de, D: Deserializer<'de>>(deserializer: D)
+    -> Result<Option<char>, D::Error>
+{
+    let gender = String::deserialize(deserializer)?;
+    Ok(gender.chars().next())
+}
+
+fn default_evs() -> Stats { Stats::all(0) }
+fn default_ivs() -> Stats { Stats::all(31) }
+fn default_level() -> u32 { 100 }
+fn default_happiness() -> u32 { 255 }
+fn default_dynamax_level() -> u32 { 10 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use super::{PokemonSet, Team, pack_name, unpack_name};
+    use battle::Request;
+
+    fn read(path: &str) -> String {
+        let mut contents = String::new();
+        File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn export_test() {
+        for path in &["data/team_gen9ou.txt", "data/team_gen3ou.txt"] {
+            let text = read(path);
+            let team = Team::from_export(&text).unwrap();
+            assert_eq!(team.pokemon.len(), 6);
+            assert_eq!(team.to_export(), text);
+
+            // Every name in the samples survives packing
+            let packed = team.to_packed();
+            assert_eq!(Team::from_packed(&packed).unwrap(), team);
+
+            let json = team.to_json().unwrap();
+            assert_eq!(Team::from_json(&json).unwrap(), team);
+        }
+
+        let set = &Team::from_export(&read("data/team_gen3ou.txt"))
+            .unwrap().pokemon[0];
+        assert_eq!(set.name, Some("Ruin".to_string()));
+        assert_eq!(set.species, "Tyranitar");
+        assert_eq!(set.gender, Some('M'));
+        assert_eq!(set.ivs.atk, 30);
+        assert_eq!(set.moves[3], "Hidden Power Bug");
+    }
+
+    #[test]
+    fn packed_test() {
+        let team = Team::from_export(&read("data/team_gen9ou.txt")).unwrap();
+        assert_eq!(team.to_packed(), read("data/team_gen9ou.packed").trim());
+
+        // Showdown's packed sets from before tera types have fewer fields
+        let set = PokemonSet::unpack(
+            "Sparky|Pikachu|lightball|Static|thunderbolt,uturn||,,,,,|F||S|50|")
+            .unwrap();
+        assert_eq!(set.name, Some("Sparky".to_string()));
+        assert_eq!(set.item, Some("lightball".to_string()));
+        assert_eq!(set.moves, vec!["thunderbolt", "uturn"]);
+        assert_eq!(set.nature, None);
+        assert_eq!(set.level, 50);
+        assert!(set.shiny);
+
+        assert!(PokemonSet::unpack("Pikachu||||||x").is_err());
+        assert_eq!(pack_name("U-turn"), "Uturn");
+        assert_eq!(unpack_name("10000000VoltThunderbolt"),
+                   "10000000 Volt Thunderbolt");
+    }
+
+    #[test]
+    fn request_test() {
+        let request = Request::parse(r#"{"side": {"pokemon": [
+            {"ident": "p1: Sparky", "details": "Pikachu, L84, F",
+             "condition": "100/100", "moves": ["thunderbolt"],
+             "baseAbility": "static", "item": "", "teraType": "Electric"}
+        ]}}"#).unwrap();
+        let set = PokemonSet::from_request(&request.side.pokemon[0]).unwrap();
+        assert_eq!(set.name, Some("Sparky".to_string()));
+        assert_eq!(set.species, "Pikachu");
+        assert_eq!(set.item, None);
+        assert_eq!(set.level, 84);
+        assert_eq!(set.tera_type, Some("Electric".to_string()));
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use serde::{Deserialize, Deserializer};
+use serde_json;
+
+use helpers::sanitize;
+use ladder::{Outcome, Record};
+use protocol::Params;
+
+/// A node of an elimination bracket. Leaves are players, the other nodes
+/// are the matches between the winners of their children.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct BracketNode {
+    /// The player, or the winner of the match once it is finished.
+    #[serde(default)]
+    pub team: Option<String>,
+    /// One of `unavailable`, `available`, `challenging`, `inprogress` or
+    /// `finished`.
+    #[serde(default)]
+    pub state: Option<String>,
+    /// `win` or `loss`, from the point of view of the first child.
+    #[serde(default)]
+    pub result: Option<String>,
+    #[serde(default)]
+    pub score: Option<Vec<u32>>,
+    /// The battle room of a match in progress.
+    #[serde(default)]
+    pub room: Option<String>,
+    #[serde(default)]
+    pub children: Vec<BracketNode>,
+}
+
+impl BracketNode {
+    /// The round the match of this node is in, counting from 1 for the
+    /// matches between leaves. Leaves are in round 0.
+    pub fn round(&self) -> u32 {
+        self.children.iter().map(|c| c.round() + 1).max().unwrap_or(0)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.state.as_ref().map(|s| &s[..]) == Some("finished")
+    }
+
+    /// The lowest round with a match that is not finished.
+    fn current_round(&self) -> Option<u32> {
+        if self.children.is_empty() || self.is_finished() {
+            return None;
+        }
+        self.children.iter()
+            .filter_map(|c| c.current_round())
+            .min()
+            .or_else(|| Some(self.round()))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct TableHeaders {
+    #[serde(default)]
+    pub cols: Vec<String>,
+    #[serde(default)]
+    pub rows: Vec<String>,
+}
+
+/// A match of a round robin, between the players of its row and column.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct TableCell {
+    #[serde(default)]
+    pub state: Option<String>,
+    /// `win`, `loss` or `draw`, from the point of view of the row.
+    #[serde(default)]
+    pub result: Option<String>,
+    #[serde(default)]
+    pub score: Option<Vec<u32>>,
+    #[serde(default)]
+    pub room: Option<String>,
+}
+
+/// The bracket of a tournament. Elimination tournaments send a `tree` with
+/// a root node, round robins a `table` with a cell for every match.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bracket {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub root_node: Option<BracketNode>,
+    #[serde(default)]
+    pub table_headers: Option<TableHeaders>,
+    #[serde(default)]
+    pub table_contents: Vec<Vec<Option<TableCell>>>,
+    /// The score of each row of the table.
+    #[serde(default)]
+    pub scores: Vec<f64>,
+}
+
+/// A change to a tournament, sent as `|tournament|update|JSON`. Only the
+/// fields that changed are sent.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TournamentUpdate {
+    #[serde(default)]
+    pub format: Option<String>,
+    #[serde(default)]
+    pub generator: Option<String>,
+    #[serde(default)]
+    pub player_cap: Option<u32>,
+    #[serde(default)]
+    pub is_started: Option<bool>,
+    /// True if the bot is a player.
+    #[serde(default)]
+    pub is_joined: Option<bool>,
+    #[serde(default)]
+    pub bracket_data: Option<Bracket>,
+    /// The players the bot may challenge.
+    #[serde(default)]
+    pub challenges: Option<Vec<String>>,
+    /// The player challenging the bot. `Some(None)` if the challenge is
+    /// gone.
+    #[serde(default, deserialize_with = "nullable")]
+    pub challenged: Option<Option<String>>,
+    /// The player the bot is challenging. `Some(None)` if the challenge is
+    /// gone.
+    #[serde(default, deserialize_with = "nullable")]
+    pub challenging: Option<Option<String>>,
+}
+
+/// Tells a field sent as `null` apart from a missing one.
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+    where D: Deserializer<'de>,
+          T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// The outcome of a tournament, sent as `|tournament|end|JSON`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TournamentEnd {
+    /// The players by placing, winners first.
+    #[serde(default)]
+    pub results: Vec<Vec<String>>,
+    #[serde(default)]
+    pub format: String,
+    #[serde(default)]
+    pub generator: String,
+    #[serde(default)]
+    pub bracket_data: Option<Bracket>,
+}
+
+/// A finished tournament battle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchResult {
+    pub user1: String,
+    pub user2: String,
+    /// The outcome for `user1`.
+    pub outcome: Outcome,
+    pub score: Vec<u32>,
+    /// False if the battle did not count, e.g. because it was tied.
+    pub recorded: bool,
+    pub room: String,
+}
+
+/// An event of the tournament protocol, sent as `|tournament|COMMAND|...`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TournamentEvent {
+    Create {
+        format: String,
+        generator: String,
+        player_cap: Option<u32>,
+    },
+    Update(TournamentUpdate),
+    /// Ends a batch of updates.
+    UpdateEnd,
+    Error(String),
+    ForceEnd,
+    Join(String),
+    Leave(String),
+    Replace {
+        old: String,
+        new: String,
+    },
+    /// Starts the tournament with a number of players.
+    Start(u32),
+    Disqualify(String),
+    BattleStart {
+        user1: String,
+        user2: String,
+        room: String,
+    },
+    BattleEnd(MatchResult),
+    End(TournamentEnd),
+    /// Any other command, like `autostart` or `scouting`, with its
+    /// parameters.
+    Other(String, Vec<String>),
+}
+
+impl TournamentEvent {
+    /// Parses a line of the tournament protocol. Returns `None` for lines
+    /// that are not tournament events.
+    pub fn parse(line: &str) -> ::Result<Option<TournamentEvent>> {
+        let p = Params::new(line);
+        if p.command != "tournament" {
+            return Ok(None);
+        }
+
+        let event = match p.get(0)? {
+            "create" => TournamentEvent::Create {
+                format: p.string(1)?.into_owned(),
+                generator: p.optional(2).into_owned(),
+                player_cap: p.get(3).ok()
+                    .and_then(|c| c.parse::<u32>().ok())
+                    .filter(|&c| c > 0),
+            },
+            "update" => TournamentEvent::Update(
+                serde_json::from_str(&p.rest(1)?)?),
+            "updateEnd" => TournamentEvent::UpdateEnd,
+            "error" => TournamentEvent::Error(p.rest(1)?.into_owned()),
+            "forceend" => TournamentEvent::ForceEnd,
+            "join" => TournamentEvent::Join(p.string(1)?.into_owned()),
+            "leave" => TournamentEvent::Leave(p.string(1)?.into_owned()),
+            "replace" => TournamentEvent::Replace {
+                old: p.string(1)?.into_owned(),
+                new: p.string(2)?.into_owned(),
+            },
+            "start" => TournamentEvent::Start(p.number(1)?),
+            "disqualify" =>
+                TournamentEvent::Disqualify(p.string(1)?.into_owned()),
+            "battlestart" => TournamentEvent::BattleStart {
+                user1: p.string(1)?.into_owned(),
+                user2: p.string(2)?.into_owned(),
+                room: p.string(3)?.into_owned(),
+            },
+            "battleend" => TournamentEvent::BattleEnd(MatchResult {
+                user1: p.string(1)?.into_owned(),
+                user2: p.string(2)?.into_owned(),
+                outcome: p.with(3, outcome)?,
+                score: p.with(4, score)?,
+                recorded: p.optional(5) != "fail",
+                room: p.optional(6).into_owned(),
+            }),
+            "end" => TournamentEvent::End(serde_json::from_str(&p.rest(1)?)?),
+            command => TournamentEvent::Other(
+                command.to_string(),
+                p.list(1).into_iter().map(|s| s.into_owned()).collect()),
+        };
+
+        Ok(Some(event))
+    }
+}
+
+fn outcome(s: &str) -> Option<Outcome> {
+    match s {
+        "win" => Some(Outcome::Win),
+        "loss" => Some(Outcome::Loss),
+        "draw" => Some(Outcome::Tie),
+        _ => None,
+    }
+}
+
+fn score(s: &str) -> Option<Vec<u32>> {
+    s.split(',').map(|n| n.trim().parse().ok()).collect()
+}
+
+/// The state of the tournament in a room, built from its events.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tournament {
+    pub room: String,
+    pub format: String,
+    /// E.g. `Single Elimination` or `Round Robin`.
+    pub generator: String,
+    pub player_cap: Option<u32>,
+    pub players: Vec<String>,
+    pub disqualified: Vec<String>,
+    pub started: bool,
+    /// True if the bot is a player.
+    pub joined: bool,
+    pub bracket: Option<Bracket>,
+    /// The players the bot may challenge.
+    pub challenges: Vec<String>,
+    /// The player challenging the bot.
+    pub challenged: Option<String>,
+    /// The player the bot is challenging.
+    pub challenging: Option<String>,
+    /// The battles being played, from room to players.
+    pub battles: BTreeMap<String, (String, String)>,
+    /// The finished battles, oldest first.
+    pub results: Vec<MatchResult>,
+    /// The players by placing, once the tournament is over.
+    pub placings: Vec<Vec<String>>,
+    pub ended: bool,
+}
+
+impl Tournament {
+    pub fn new(room: &str) -> Tournament {
+        Tournament {
+            room: room.to_string(),
+            ..Tournament::default()
+        }
+    }
+
+    /// Updates the state with an event.
+    pub fn apply(&mut self, event: &TournamentEvent) {
+        match *event {
+            TournamentEvent::Create { ref format, ref generator,
+                                      player_cap } => {
+                *self = Tournament {
+                    format: format.clone(),
+                    generator: generator.clone(),
+                    player_cap,
+                    ..Tournament::new(&self.room)
+                };
+            },
+            TournamentEvent::Update(ref update) => self.update(update),
+            TournamentEvent::ForceEnd => self.ended = true,
+            TournamentEvent::Join(ref user) => {
+                if !self.is_player(user) {
+                    self.players.push(user.clone());
+                }
+            },
+            TournamentEvent::Leave(ref user) => {
+                let id = sanitize(user);
+                self.players.retain(|p| sanitize(p) != id);
+            },
+            TournamentEvent::Replace { ref old, ref new } => {
+                let id = sanitize(old);
+                for player in self.players.iter_mut()
+                    .filter(|p| sanitize(p) == id) {
+                    *player = new.clone();
+                }
+            },
+            TournamentEvent::Start(_) => self.started = true,
+            TournamentEvent::Disqualify(ref user) => {
+                self.disqualified.push(user.clone());
+            },
+            TournamentEvent::BattleStart { ref user1, ref user2, ref room } => {
+                self.battles.insert(room.clone(),
+                                    (user1.clone(), user2.clone()));
+            },
+            TournamentEvent::BattleEnd(ref result) => {
+                self.battles.remove(&result.room);
+                self.results.push(result.clone());
+            },
+            TournamentEvent::End(ref end) => {
+                self.ended = true;
+                self.placings = end.results.clone();
+                if end.bracket_data.is_some() {
+                    self.bracket = end.bracket_data.clone();
+                }
+            },
+            TournamentEvent::UpdateEnd |
+            TournamentEvent::Error(_) |
+            TournamentEvent::Other(..) => (),
+        }
+    }
+
+    fn update(&mut self, update: &TournamentUpdate) {
+        if let Some(ref format) = update.format {
+            self.format = format.clone();
+        }
+        if let Some(ref generator) = update.generator {
+            self.generator = generator.clone();
+        }
+        if let Some(cap) = update.player_cap {
+            self.player_cap = Some(cap).filter(|&c| c > 0);
+        }
+        if let Some(started) = update.is_started {
+            self.started = started;
+        }
+        if let Some(joined) = update.is_joined {
+            self.joined = joined;
+        }
+        if update.bracket_data.is_some() {
+            self.bracket = update.bracket_data.clone();
+        }
+        if let Some(ref challenges) = update.challenges {
+            self.challenges = challenges.clone();
+        }
+        if let Some(ref challenged) = update.challenged {
+            self.challenged = challenged.clone();
+        }
+        if let Some(ref challenging) = update.challenging {
+            self.challenging = challenging.clone();
+        }
+    }
+
+    pub fn is_player(&self, user: &str) -> bool {
+        let id = sanitize(user);
+        self.players.iter().any(|p| sanitize(p) == id)
+    }
+
+    /// Returns the round of an elimination tournament being played,
+    /// counting from 1. `None` before the start, after the end, and for
+    /// round robins.
+    pub fn round(&self) -> Option<u32> {
+        if !self.started || self.ended {
+            return None;
+        }
+        self.bracket.as_ref()
+            .and_then(|b| b.root_node.as_ref())
+            .and_then(|root| root.current_round())
+    }
+
+    /// Returns the tally of the recorded battles of a player.
+    pub fn record(&self, user: &str) -> Record {
+        let id = sanitize(user);
+        let mut record = Record::default();
+        for result in self.results.iter().filter(|r| r.recorded) {
+            let outcome = if sanitize(&result.user1) == id {
+                result.outcome
+            } else if sanitize(&result.user2) == id {
+                match result.outcome {
+                    Outcome::Win => Outcome::Loss,
+                    Outcome::Loss => Outcome::Win,
+                    Outcome::Tie => Outcome::Tie,
+                }
+            } else {
+                continue;
+            };
+            match outcome {
+                Outcome::Win => record.wins += 1,
+                Outcome::Loss => record.losses += 1,
+                Outcome::Tie => record.ties += 1,
+            }
+        }
+        record
+    }
+
+    /// Returns the winner, once the tournament is over.
+    pub fn winner(&self) -> Option<&str> {
+        self.placings.first()
+            .and_then(|p| p.first())
+            .map(|w| &w[..])
+    }
+}
+
+/// Is told about every tournament event, once it is applied to the
+/// `Tournament` of its room. Updates come in batches ended by
+/// `TournamentEvent::UpdateEnd`.
+pub trait TournamentListener: Send + Debug {
+    fn on_event(&mut self, event: &TournamentEvent, tournament: &Tournament);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tournament, TournamentEvent};
+    use ladder::Outcome;
+
+    fn apply(tournament: &mut Tournament, line: &str) {
+        let event = TournamentEvent::parse(line).unwrap().unwrap();
+        tournament.apply(&event);
+    }
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(TournamentEvent::parse("|tournament|create|gen9ou|\
+                                            Single Elimination|0").unwrap(),
+                   Some(TournamentEvent::Create {
+                       format: "gen9ou".to_string(),
+                       generator: "Single Elimination".to_string(),
+                       player_cap: None,
+                   }));
+        match TournamentEvent::parse("|tournament|battleend|Alice|Bob|\
+                                      win|1,0|success|battle-gen9ou-7") {
+            Ok(Some(TournamentEvent::BattleEnd(result))) => {
+                assert_eq!(result.outcome, Outcome::Win);
+                assert_eq!(result.score, vec![1, 0]);
+                assert!(result.recorded);
+                assert_eq!(result.room, "battle-gen9ou-7");
+            },
+            e => panic!("not a battle end: {:?}", e),
+        }
+        assert!(TournamentEvent::parse("|tournament|battleend|Alice|Bob|\
+                                        won|1,0|success|").is_err());
+        assert_eq!(TournamentEvent::parse("|tournament|autostart|off")
+                   .unwrap(),
+                   Some(TournamentEvent::Other("autostart".to_string(),
+                                               vec!["off".to_string()])));
+        assert_eq!(TournamentEvent::parse("|join|Alice").unwrap(), None);
+    }
+
+    #[test]
+    fn apply_test() {
+        let mut tour = Tournament::new("techcode");
+        for line in &[
+            "|tournament|create|gen9ou|Single Elimination|4",
+            "|tournament|update|{\"format\":\"gen9ou\",\"isStarted\":false}",
+            "|tournament|join|Alice",
+            "|tournament|join|Bob",
+            "|tournament|join|Carol",
+            "|tournament|leave|Carol",
+            "|tournament|start|2",
+            "|tournament|update|{\"isStarted\":true,\"challenged\":\"Alice\",\
+             \"bracketData\":{\"type\":\"tree\",\"rootNode\":{\
+             \"state\":\"inprogress\",\"room\":\"battle-gen9ou-7\",\
+             \"children\":[{\"team\":\"Alice\"},{\"team\":\"Bob\"}]}}}",
+            "|tournament|updateEnd",
+            "|tournament|battlestart|Alice|Bob|battle-gen9ou-7",
+        ] {
+            apply(&mut tour, line);
+        }
+        assert_eq!(tour.player_cap, Some(4));
+        assert_eq!(tour.players, vec!["Alice", "Bob"]);
+        assert!(tour.started && !tour.joined);
+        assert_eq!(tour.round(), Some(1));
+        assert_eq!(tour.challenged, Some("Alice".to_string()));
+        assert_eq!(tour.battles.len(), 1);
+
+        apply(&mut tour, "|tournament|update|{\"challenged\":null}");
+        assert_eq!(tour.challenged, None);
+        assert_eq!(tour.format, "gen9ou");
+
+        apply(&mut tour, "|tournament|battleend|Alice|Bob|loss|0,1|success|\
+                          battle-gen9ou-7");
+        apply(&mut tour, "|tournament|end|{\"results\":[[\"Bob\"]],\
+                          \"format\":\"gen9ou\",\"generator\":\
+                          \"Single Elimination\",\"bracketData\":{\
+                          \"type\":\"tree\",\"rootNode\":{\"team\":\"Bob\",\
+                          \"state\":\"finished\",\"result\":\"loss\",\
+                          \"children\":[{\"team\":\"Alice\"},\
+                          {\"team\":\"Bob\"}]}}}");
+        assert!(tour.battles.is_empty());
+        assert_eq!(tour.record("bob").wins, 1);
+        assert_eq!(tour.record("Alice").losses, 1);
+        assert_eq!(tour.winner(), Some("Bob"));
+        assert_eq!(tour.round(), None);
+
+        // A new tournament starts from scratch
+        apply(&mut tour, "|tournament|create|gen9randombattle|Round Robin|");
+        assert!(tour.players.is_empty() && !tour.ended);
+        assert_eq!(tour.room, "techcode");
+    }
+}
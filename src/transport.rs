@@ -0,0 +1,430 @@
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex, mpsc};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use native_tls::{Certificate, HandshakeError, TlsConnector, TlsStream};
+use websocket::{ClientBuilder, Message};
+use websocket::message::Type;
+use websocket::receiver::Reader;
+use websocket::sender::Writer;
+use websocket::sync::stream::ReadWritePair;
+use websocket::url::Url;
+
+/// How long the thread driving a secure connection blocks on a read before
+/// it writes what was queued in the meantime.
+const TLS_READ_TIMEOUT_MS: u64 = 50;
+
+/// A single frame sent to or received from the server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+/// A `Transport` carries frames between the `Bot` and a server. The bot
+/// sends and receives on separate threads, so a transport must be usable
+/// from both at once.
+pub trait Transport: Send + Sync + 'static {
+    /// Sends a frame to the server.
+    fn send_frame(&self, frame: Frame) -> ::Result<()>;
+
+    /// Blocks until the next frame arrives. Returns `Frame::Close` once the
+    /// connection has been closed.
+    fn recv_frame(&self) -> ::Result<Frame>;
+
+    /// Closes the connection.
+    fn close(&self) -> ::Result<()>;
+}
+
+type SocketReader = Reader<Box<Read + Send>>;
+type SocketWriter = Writer<Box<Write + Send>>;
+
+/// The default `Transport`, a websocket connection to a Showdown server.
+pub struct WebSocketTransport {
+    reader: Mutex<SocketReader>,
+    writer: Mutex<SocketWriter>,
+    /// The socket under the websocket, shut down to unblock a receive.
+    socket: TcpStream,
+}
+
+impl WebSocketTransport {
+    /// Opens the websocket described by the config. Reads and writes go
+    /// through independent halves of the socket. A `wss` stream cannot be
+    /// split, so a thread of its own drives it and passes the bytes on.
+    pub fn connect(config: &::Config) -> ::Result<WebSocketTransport> {
+        let url = Url::parse(
+            &format!("{}://{}:{}{}",
+            &config.scheme,
+            &config.host,
+            &config.port,
+            &config.path))?;
+
+        info!("Connecting to {}", url);
+        let socket = TcpStream::connect(
+            format!("{}:{}", config.host, config.port))?;
+        let (read, write) = match &*config.scheme {
+            "ws" => (Box::new(socket.try_clone()?) as Box<Read + Send>,
+                     Box::new(socket.try_clone()?) as Box<Write + Send>),
+            "wss" => {
+                let connector = tls_connector(config)?;
+                let stream = match connector.connect(&config.host,
+                                                     socket.try_clone()?) {
+                    Ok(stream) => stream,
+                    Err(HandshakeError::Failure(e)) => return Err(e.into()),
+                    Err(HandshakeError::Interrupted(_)) =>
+                        return Err(::Error::Io(io::Error::new(
+                            io::ErrorKind::Interrupted,
+                            "TLS handshake interrupted"))),
+                };
+                let (read, write) = drive_tls(stream)?;
+                (Box::new(read) as Box<Read + Send>,
+                 Box::new(write) as Box<Write + Send>)
+            },
+            scheme => return Err(::Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported websocket scheme: {}", scheme)))),
+        };
+
+        let client = ClientBuilder::from_url(&url)
+            .connect_on(ReadWritePair(read, write))?;
+        let (reader, writer) = client.split()?;
+
+        info!("Successfully connected");
+        Ok(WebSocketTransport {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+            socket,
+        })
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send_frame(&self, frame: Frame) -> ::Result<()> {
+        let message = match frame {
+            Frame::Text(text) => Message::text(text),
+            Frame::Ping(data) => Message::ping(data),
+            Frame::Pong(data) => Message::pong(data),
+            Frame::Close => Message::close(),
+        };
+        Ok(self.writer.lock().unwrap().send_message(&message)?)
+    }
+
+    fn recv_frame(&self) -> ::Result<Frame> {
+        let message = self.reader.lock().unwrap().recv_message()?;
+        match message.opcode {
+            Type::Close => Ok(Frame::Close),
+            Type::Ping => Ok(Frame::Ping(message.payload.into_owned())),
+            Type::Pong => Ok(Frame::Pong(message.payload.into_owned())),
+            _ => match String::from_utf8(message.payload.into_owned()) {
+                Ok(s) => Ok(Frame::Text(s)),
+                Err(e) => Err(::Error::Io(
+                    io::Error::new(io::ErrorKind::InvalidData, e))),
+            },
+        }
+    }
+
+    /// Sends a close frame and shuts the socket down so that a thread
+    /// blocked receiving returns.
+    fn close(&self) -> ::Result<()> {
+        let _ = self.writer.lock().unwrap().send_message(&Message::close());
+        match self.socket.shutdown(Shutdown::Both) {
+            // Already shut down by the server
+            Err(ref e) if e.kind() == io::ErrorKind::NotConnected => Ok(()),
+            result => Ok(result?),
+        }
+    }
+}
+
+/// Builds the TLS connector, trusting the PEM encoded `ca_file` on top of
+/// the system roots if one is configured.
+fn tls_connector(config: &::Config) -> ::Result<TlsConnector> {
+    let mut builder = TlsConnector::builder()?;
+    if let Some(ref path) = config.ca_file {
+        let mut pem = Vec::new();
+        File::open(path)?.read_to_end(&mut pem)?;
+        builder.add_root_certificate(Certificate::from_pem(&pem)?)?;
+    }
+    Ok(builder.build()?)
+}
+
+/// Drives a TLS stream on a thread of its own and returns pipes to read
+/// and write through it. The thread reads with a timeout to write what was
+/// queued in the meantime. Unlike a websocket frame, which may be half read
+/// when the timeout hits, a TLS read can be retried.
+fn drive_tls(stream: TlsStream<TcpStream>)
+    -> io::Result<(PipeReader, PipeWriter)>
+{
+    stream.get_ref().set_read_timeout(
+        Some(Duration::from_millis(TLS_READ_TIMEOUT_MS)))?;
+    let (incoming_tx, incoming_rx) = mpsc::channel();
+    let (outgoing_tx, outgoing_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        if let Err(e) = pump_tls(stream, &incoming_tx, &outgoing_rx) {
+            debug!("TLS stream closed: {}", e);
+        }
+    });
+
+    Ok((PipeReader { rx: incoming_rx, buf: Vec::new(), pos: 0 },
+        PipeWriter { tx: outgoing_tx }))
+}
+
+fn pump_tls(mut stream: TlsStream<TcpStream>, incoming: &Sender<Vec<u8>>,
+            outgoing: &Receiver<Vec<u8>>) -> io::Result<()> {
+    let mut buf = [0; 16 * 1024];
+    loop {
+        loop {
+            match outgoing.try_recv() {
+                Ok(bytes) => stream.write_all(&bytes)?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        stream.flush()?;
+
+        match stream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                if incoming.send(buf[..n].to_vec()).is_err() {
+                    return Ok(());
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                e.kind() == io::ErrorKind::TimedOut => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The bytes read by the thread driving a TLS stream. Reads block until
+/// there are some, and return 0 once the stream is closed.
+struct PipeReader {
+    rx: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            match self.rx.recv() {
+                Ok(buf) => {
+                    self.buf = buf;
+                    self.pos = 0;
+                },
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = cmp::min(out.len(), self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Queues bytes for the thread driving a TLS stream to write.
+struct PipeWriter {
+    tx: Sender<Vec<u8>>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.tx.send(buf.to_vec()) {
+            Ok(()) => Ok(buf.len()),
+            Err(_) => Err(io::Error::new(io::ErrorKind::BrokenPipe,
+                                         "TLS stream closed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An in-memory `Transport` for tests. Create one with
+/// `MemoryTransport::pair` and script the server side with the returned
+/// `MemoryServer`, using raw protocol lines such as `|challstr|...` or
+/// `>room\n|c:|...`.
+#[derive(Clone)]
+pub struct MemoryTransport {
+    incoming: Arc<Mutex<mpsc::Receiver<Frame>>>,
+    outgoing: Arc<Mutex<mpsc::Sender<Frame>>>,
+}
+
+/// The server end of a `MemoryTransport`.
+pub struct MemoryServer {
+    tx: mpsc::Sender<Frame>,
+    rx: mpsc::Receiver<Frame>,
+}
+
+impl MemoryTransport {
+    /// Creates a connected transport and server.
+    pub fn pair() -> (MemoryTransport, MemoryServer) {
+        let (server_tx, bot_rx) = mpsc::channel();
+        let (bot_tx, server_rx) = mpsc::channel();
+        let transport = MemoryTransport {
+            incoming: Arc::new(Mutex::new(bot_rx)),
+            outgoing: Arc::new(Mutex::new(bot_tx)),
+        };
+        (transport, MemoryServer { tx: server_tx, rx: server_rx })
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send_frame(&self, frame: Frame) -> ::Result<()> {
+        // The server hanging up is the same as the socket closing
+        let _ = self.outgoing.lock().unwrap().send(frame);
+        Ok(())
+    }
+
+    fn recv_frame(&self) -> ::Result<Frame> {
+        match self.incoming.lock().unwrap().recv() {
+            Ok(frame) => Ok(frame),
+            Err(_) => Ok(Frame::Close),
+        }
+    }
+
+    fn close(&self) -> ::Result<()> {
+        self.send_frame(Frame::Close)
+    }
+}
+
+impl MemoryServer {
+    /// Sends a raw protocol frame to the bot.
+    pub fn send_line<S: Into<String>>(&self, line: S) {
+        let _ = self.tx.send(Frame::Text(line.into()));
+    }
+
+    /// Sends any frame to the bot.
+    pub fn send_frame(&self, frame: Frame) {
+        let _ = self.tx.send(frame);
+    }
+
+    /// Closes the connection from the server side.
+    pub fn close(&self) {
+        let _ = self.tx.send(Frame::Close);
+    }
+
+    /// Waits up to `timeout` for the next frame sent by the bot.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Frame> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+
+    /// Returns the text of every frame the bot has sent so far.
+    pub fn lines(&self) -> Vec<String> {
+        self.rx.try_iter()
+            .filter_map(|f| match f {
+                Frame::Text(s) => Some(s),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::sync::{Arc, mpsc};
+    use std::thread;
+    use std::time::Duration;
+
+    use native_tls::{Pkcs12, TlsAcceptor};
+    use websocket::Message as WebSocketMessage;
+    use websocket::message::Type;
+    use websocket::sync::Server;
+
+    use super::{Frame, Transport, WebSocketTransport};
+    use config::Config;
+
+    static TEST_PATH: &'static str = "examples/example_config.toml";
+
+    /// A config for a websocket on a local port, trusting the test
+    /// certificate.
+    fn socket_config(scheme: &str, port: u16) -> Config {
+        let mut config = Config::new(TEST_PATH).unwrap();
+        config.scheme = scheme.to_string();
+        config.host = "localhost".to_string();
+        config.port = port.to_string();
+        config.path = "/showdown/websocket".to_string();
+        config.ca_file = Some("examples/tls/localhost.pem".to_string());
+        config
+    }
+
+    #[test]
+    fn tls_test() {
+        let mut der = Vec::new();
+        File::open("examples/tls/localhost.p12").unwrap()
+            .read_to_end(&mut der).unwrap();
+        let identity = Pkcs12::from_der(&der, "showdown").unwrap();
+        let acceptor = TlsAcceptor::builder(identity).unwrap().build().unwrap();
+        let mut server = Server::bind_secure("127.0.0.1:0", acceptor).unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let text = "x".repeat(1 << 20);
+        let expected = Frame::Text(text.clone());
+        let serve = thread::spawn(move || {
+            let mut client = server.accept().ok().unwrap()
+                .accept().ok().unwrap();
+
+            // Send a large frame in two parts, with a pause longer than the
+            // read timeout in between
+            let len = text.len() as u64;
+            let mut frame = vec![0x81, 127];
+            frame.extend((0..8).rev().map(|i| (len >> (i * 8)) as u8));
+            frame.extend_from_slice(text.as_bytes());
+            let (first, rest) = frame.split_at(1000);
+            client.writer_mut().write_all(first).unwrap();
+            client.writer_mut().flush().unwrap();
+            thread::sleep(Duration::from_millis(200));
+            client.writer_mut().write_all(rest).unwrap();
+            client.writer_mut().flush().unwrap();
+
+            let reply: WebSocketMessage = client.recv_message().unwrap();
+            String::from_utf8(reply.payload.into_owned()).unwrap()
+        });
+
+        let transport =
+            WebSocketTransport::connect(&socket_config("wss", port)).unwrap();
+        assert!(transport.recv_frame().unwrap() == expected);
+        transport.send_frame(Frame::Text("|/join lobby".to_string())).unwrap();
+        assert_eq!(serve.join().unwrap(), "|/join lobby");
+    }
+
+    #[test]
+    fn close_test() {
+        let mut server = Server::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let serve = thread::spawn(move || {
+            // Keep the connection open without sending anything
+            let mut client = server.accept().ok().unwrap()
+                .accept().ok().unwrap();
+            let message: WebSocketMessage = client.recv_message().unwrap();
+            message.opcode == Type::Close
+        });
+
+        let transport = Arc::new(
+            WebSocketTransport::connect(&socket_config("ws", port)).unwrap());
+        let receiver = transport.clone();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let closed = match receiver.recv_frame() {
+                Ok(Frame::Close) | Err(_) => true,
+                Ok(_) => false,
+            };
+            tx.send(closed).unwrap();
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        transport.close().unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(true));
+        assert!(serve.join().unwrap(), "no close frame was sent");
+    }
+}
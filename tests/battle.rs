@@ -0,0 +1,107 @@
+extern crate showdown;
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{run_script, test_bot};
+use showdown::{Message, Plugin};
+use showdown::battle::RandomAgent;
+
+/// Records the room and line of every message the plugins are given.
+#[derive(Debug, Default)]
+struct SeenPlugin(Arc<Mutex<Vec<(String, String)>>>);
+
+impl Plugin for SeenPlugin {
+    fn new() -> Box<Plugin> {
+        Box::new(SeenPlugin::default())
+    }
+
+    fn is_match(&self, _: &Message) -> bool {
+        true
+    }
+
+    fn handle(&mut self, msg: &Message) {
+        self.0.lock().unwrap()
+            .push((msg.room_id.to_string(), msg.line.to_string()));
+    }
+}
+
+#[test]
+fn battle_agent_test() {
+    let b = test_bot();
+    b.set_agent(RandomAgent::new());
+
+    run_script(b, &[
+        ">battle-gen9ou-1\n|init|battle",
+        concat!(
+            ">battle-gen9ou-1\n|request|{\"active\":[{\"trapped\":true,",
+            "\"moves\":[{\"move\":\"Tackle\",\"id\":\"tackle\"}]}],",
+            "\"side\":{\"pokemon\":[{\"ident\":\"p1: A\",\"details\":\"A\",",
+            "\"condition\":\"100/100\",\"active\":true}]},\"rqid\":2}"),
+        ">battle-gen9ou-1\n|\n|turn|1",
+    ], &["battle-gen9ou-1|/choose move 1|2"]);
+}
+
+#[test]
+fn forced_switch_test() {
+    let b = test_bot();
+    b.set_agent(RandomAgent::new());
+
+    // The request is the last frame, so nothing else can trigger the agent
+    run_script(b, &[
+        ">battle-gen9ou-4\n|init|battle\n|\n|upkeep",
+        concat!(
+            ">battle-gen9ou-4\n|request|{\"forceSwitch\":[true],",
+            "\"side\":{\"pokemon\":[{\"ident\":\"p1: A\",\"details\":\"A\",",
+            "\"condition\":\"0 fnt\",\"active\":true},{\"ident\":\"p1: B\",",
+            "\"details\":\"B\",\"condition\":\"100/100\"}]},\"rqid\":3}"),
+    ], &["battle-gen9ou-4|/choose switch 2|3"]);
+}
+
+#[test]
+fn battle_state_test() {
+    let b = test_bot();
+    let player = b.clone();
+    b.set_agent(RandomAgent::new());
+
+    run_script(b, &[concat!(
+        ">battle-gen9ou-5\n|init|battle\n|gen|9\n|tier|[Gen 9] OU\n",
+        "|turn|1")], &[]);
+
+    let state = player.battle("battle-gen9ou-5").unwrap();
+    assert_eq!(state.room, "battle-gen9ou-5");
+    assert_eq!(state.gen, 9);
+    assert_eq!(state.turn, 1);
+}
+
+#[test]
+fn spectated_battle_test() {
+    let b = test_bot();
+    let spectator = b.clone();
+
+    run_script(b, &[">battle-gen9ou-2\n|init|battle\n|turn|1",
+                    ">techcode\n|init|chat\n|turn|1"], &[]);
+
+    assert_eq!(spectator.battle("battle-gen9ou-2").unwrap().turn, 1);
+    assert!(spectator.battle("techcode").is_none());
+}
+
+#[test]
+fn bad_battle_event_test() {
+    let b = test_bot();
+    let watcher = b.clone();
+    let seen = SeenPlugin::default();
+    let lines = seen.0.clone();
+    b.register(Box::new(seen));
+
+    run_script(b, &[">battle-gen9ou-3\n|init|battle\n|turn|x\n|turn|2"], &[]);
+
+    let room = "battle-gen9ou-3".to_string();
+    assert_eq!(*lines.lock().unwrap(), vec![
+        (room.clone(), "|init|battle".to_string()),
+        (room.clone(), "|turn|x".to_string()),
+        (room.clone(), "|turn|2".to_string()),
+    ]);
+    assert_eq!(watcher.battle("battle-gen9ou-3").unwrap().turn, 2);
+}
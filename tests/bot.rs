@@ -0,0 +1,146 @@
+extern crate showdown;
+
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use common::{run_script, test_bot};
+use showdown::{Error, Frame, MemoryTransport, Message, Plugin};
+
+#[derive(Debug)]
+struct PingPlugin;
+
+impl Plugin for PingPlugin {
+    fn new() -> Box<Plugin> {
+        Box::new(PingPlugin)
+    }
+
+    fn is_match(&self, msg: &Message) -> bool {
+        msg.payload == "ping"
+    }
+
+    fn handle(&mut self, msg: &Message) {
+        msg.send("pong");
+    }
+}
+
+#[derive(Debug)]
+struct EchoPlugin;
+
+impl Plugin for EchoPlugin {
+    fn new() -> Box<Plugin> {
+        Box::new(EchoPlugin)
+    }
+
+    fn is_match(&self, msg: &Message) -> bool {
+        msg.payload.starts_with("echo ")
+    }
+
+    fn handle(&mut self, msg: &Message) {
+        msg.send(&msg.payload[5..]);
+    }
+}
+
+#[test]
+fn plugin_reply_test() {
+    let b = test_bot();
+    b.register(PingPlugin::new());
+
+    run_script(b, &[">techcode\n|c:|1|+someone|ping",
+                    ">techcode\n|c:|2|+someone|not a ping"],
+               &["techcode|pong"]);
+}
+
+#[test]
+fn escape_user_text_test() {
+    let b = test_bot();
+    b.register(EchoPlugin::new());
+
+    run_script(b, &[">techcode\n|c:|1|+someone|echo /roomban someone",
+                    ">techcode\n|c:|2|+someone|echo !dt pikachu"],
+               &["techcode|//roomban someone", "techcode|\u{200B}!dt pikachu"]);
+}
+
+#[test]
+fn pong_test() {
+    let (transport, server) = MemoryTransport::pair();
+    let b = test_bot();
+    let run = thread::spawn(move || b.run(move |_| Ok(transport.clone())));
+    server.send_frame(Frame::Ping(b"PING".to_vec()));
+
+    assert_eq!(server.recv_timeout(Duration::from_secs(10)),
+               Some(Frame::Pong(b"PING".to_vec())));
+    server.close();
+    run.join().unwrap().unwrap();
+}
+
+#[test]
+fn guest_join_test() {
+    let mut b = test_bot();
+    b.config.guest = true;
+    b.config.rooms = vec!["techcode".to_string()];
+
+    run_script(b, &["|challstr|4|abcdef"], &["|/join techcode"]);
+}
+
+#[test]
+fn guest_name_taken_test() {
+    let mut b = test_bot();
+    b.config.guest = true;
+    b.config.guest_name = Some("Taken".to_string());
+    b.config.rooms = vec!["techcode".to_string()];
+
+    run_script(b, &["|challstr|4|abcdef",
+                    "|nametaken|Taken|Someone is already using the name."],
+               &["|/trn Taken", "|/join techcode"]);
+}
+
+#[test]
+fn reconnect_limit_test() {
+    let mut b = test_bot();
+    b.config.reconnect = true;
+    b.config.reconnect_base_ms = 1;
+    b.config.reconnect_max_ms = 1;
+    b.config.reconnect_max_attempts = 3;
+
+    // The server drops every connection before the bot logs in
+    let mut connects = 0;
+    let result = b.run(|_| {
+        connects += 1;
+        let (transport, server) = MemoryTransport::pair();
+        server.close();
+        Ok(transport)
+    });
+
+    match result {
+        Err(Error::ReconnectLimit(3)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(connects, 4);
+}
+
+#[test]
+fn close_while_reconnecting_test() {
+    let mut b = test_bot();
+    b.config.reconnect = true;
+    b.config.reconnect_base_ms = 400;
+    b.config.reconnect_max_ms = 400;
+    let closer = b.clone();
+
+    // The bot is closed while it waits to reconnect
+    let mut connects = 0;
+    b.run(|_| {
+        connects += 1;
+        let closer = closer.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            closer.close();
+        });
+        let (transport, server) = MemoryTransport::pair();
+        server.close();
+        Ok(transport)
+    }).unwrap();
+
+    assert_eq!(connects, 1);
+}
@@ -0,0 +1,19 @@
+extern crate showdown;
+
+mod common;
+
+use common::{run_script, test_bot};
+use showdown::ChallengeFilter;
+
+#[test]
+fn challenge_policy_test() {
+    let b = test_bot();
+    let mut filter = ChallengeFilter::default();
+    filter.users = vec!["alice".to_string()];
+    b.set_challenge_policy(Box::new(filter));
+
+    let update = concat!(
+        "|updatechallenges|{\"challengesFrom\":{\"alice\":\"gen9ou\",",
+        "\"bob\":\"gen9ou\"},\"challengeTo\":null}");
+    run_script(b, &[update, update], &["|/accept alice", "|/reject bob"]);
+}
@@ -0,0 +1,46 @@
+//! Helpers shared by the integration tests, which drive a `Bot` through a
+//! `MemoryTransport`.
+
+#![allow(dead_code)]
+
+use std::thread;
+use std::time::Duration;
+
+use showdown::{Bot, Frame, MemoryTransport};
+
+pub static TEST_PATH: &'static str = "examples/example_config.toml";
+
+/// Returns a bot from the example config that does not reconnect or
+/// throttle its messages.
+pub fn test_bot() -> Bot {
+    let mut b = Bot::new(TEST_PATH).unwrap();
+    b.config.reconnect = false;
+    b.config.throttle_ms = 0;
+    b
+}
+
+/// Runs `bot` against a server that sends `frames`, and checks that the bot
+/// sent `expected` back. The server only closes the connection once the
+/// expected lines are in, since the bot drops what it has queued when the
+/// server hangs up.
+pub fn run_script(bot: Bot, frames: &[&str], expected: &[&str]) {
+    let (transport, server) = MemoryTransport::pair();
+    let run = thread::spawn(move || bot.run(move |_| Ok(transport.clone())));
+    for frame in frames {
+        server.send_line(*frame);
+    }
+
+    let mut lines = Vec::new();
+    while lines.len() < expected.len() {
+        match server.recv_timeout(Duration::from_secs(10)) {
+            Some(Frame::Text(line)) => lines.push(line),
+            Some(_) => (),
+            None => break,
+        }
+    }
+    server.close();
+    run.join().unwrap().unwrap();
+    lines.extend(server.lines());
+
+    assert_eq!(lines, expected);
+}
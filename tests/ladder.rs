@@ -0,0 +1,35 @@
+extern crate showdown;
+
+mod common;
+
+use common::{run_script, test_bot};
+
+#[test]
+fn ladder_test() {
+    let mut b = test_bot();
+    b.config.rooms = Vec::new();
+    b.config.ladder_formats = vec!["gen9randombattle".to_string()];
+    let ladder = b.clone();
+
+    let idle = "|updatesearch|{\"searching\":[],\"games\":null}";
+    run_script(b, &[
+        idle,
+        "|updateuser| Bot|1|0|{}",
+        concat!("|updatesearch|{\"searching\":[\"gen9randombattle\"],",
+                "\"games\":null}"),
+        concat!(">battle-gen9randombattle-1\n|request|{\"wait\":true,",
+                "\"side\":{\"name\":\"Bot\",\"id\":\"p1\"}}"),
+        concat!(">battle-gen9randombattle-1\n|player|p1|Bot|1|\n",
+                "|player|p2|Alice|2|\n|win|Bot"),
+        idle,
+    ], &[
+        "|/utm null", "|/search gen9randombattle",
+        "|/leave battle-gen9randombattle-1",
+        "|/utm null", "|/search gen9randombattle",
+    ]);
+
+    assert_eq!(ladder.ladder_record(Some("gen9randombattle")).wins, 1);
+    let results = ladder.ladder_results();
+    assert_eq!(results[0].room, "battle-gen9randombattle-1");
+    assert_eq!(results[0].format, "gen9randombattle");
+}
@@ -0,0 +1,86 @@
+extern crate serde_json;
+extern crate showdown;
+
+mod common;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use common::test_bot;
+use showdown::{Frame, MemoryTransport, Query, Rank, Result};
+
+#[test]
+fn query_test() {
+    let (transport, server) = MemoryTransport::pair();
+    let b = test_bot();
+    let querier = b.clone();
+    let (tx, rx) = mpsc::channel();
+    b.query_with(&Query::UserDetails("Bob".to_string()),
+                 move |details: Result<Value>| {
+                     tx.send(details.unwrap()).unwrap();
+                 });
+    let bot = thread::spawn(move || {
+        b.run(move |_| Ok(transport.clone())).unwrap();
+    });
+
+    let alice = thread::spawn(move || {
+        querier.query::<Value>(&Query::UserDetails("Alice".to_string()),
+                               Duration::from_secs(10))
+    });
+    let mut sent = Vec::new();
+    while sent.len() < 2 {
+        match server.recv_timeout(Duration::from_secs(10)) {
+            Some(Frame::Text(line)) => sent.push(line),
+            Some(_) => (),
+            None => panic!("queries not sent: {:?}", sent),
+        }
+    }
+    assert_eq!(sent, vec!["|/cmd userdetails Bob",
+                          "|/cmd userdetails Alice"]);
+
+    // Answered out of order
+    server.send_line("|queryresponse|userdetails|\
+                      {\"userid\":\"alice\",\"group\":\"+\"}");
+    server.send_line("|queryresponse|userdetails|\
+                      {\"userid\":\"bob\",\"group\":\" \"}");
+    assert_eq!(alice.join().unwrap().unwrap()["group"], "+");
+    assert_eq!(rx.recv_timeout(Duration::from_secs(10)).unwrap()["userid"],
+               "bob");
+
+    server.close();
+    bot.join().unwrap();
+}
+
+#[test]
+fn user_details_test() {
+    let (transport, server) = MemoryTransport::pair();
+    let b = test_bot();
+    let querier = b.clone();
+    let bot = thread::spawn(move || {
+        b.run(move |_| Ok(transport.clone())).unwrap();
+    });
+
+    let lookup = thread::spawn(move || {
+        let first = querier.user_details("Alice").unwrap();
+        let cached = querier.user_details("alice").unwrap();
+        (first, cached, querier.user_map.highest_rank_of_user("alice"))
+    });
+    match server.recv_timeout(Duration::from_secs(10)) {
+        Some(Frame::Text(line)) =>
+            assert_eq!(line, "|/cmd userdetails Alice"),
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+    server.send_line("|queryresponse|userdetails|{\"userid\":\"alice\",\
+                      \"name\":\"Alice\",\"group\":\"%\",\"rooms\":{}}");
+
+    let (first, cached, rank) = lookup.join().unwrap();
+    assert_eq!(first, cached);
+    assert_eq!(rank, Rank::Driver);
+
+    server.close();
+    bot.join().unwrap();
+    assert!(server.lines().is_empty());
+}
@@ -0,0 +1,74 @@
+extern crate showdown;
+
+mod common;
+
+use std::sync::{Arc, Mutex, mpsc};
+
+use common::{run_script, test_bot};
+use showdown::{Message, Plugin, Tournament, TournamentEvent,
+               TournamentListener};
+
+#[derive(Debug)]
+struct TourListener(mpsc::Sender<(TournamentEvent, Tournament)>);
+
+impl TournamentListener for TourListener {
+    fn on_event(&mut self, event: &TournamentEvent, tournament: &Tournament) {
+        self.0.send((event.clone(), tournament.clone())).unwrap();
+    }
+}
+
+/// Records the line of every message the plugins are given.
+#[derive(Debug, Default)]
+struct SeenPlugin(Arc<Mutex<Vec<String>>>);
+
+impl Plugin for SeenPlugin {
+    fn new() -> Box<Plugin> {
+        Box::new(SeenPlugin::default())
+    }
+
+    fn is_match(&self, _: &Message) -> bool {
+        true
+    }
+
+    fn handle(&mut self, msg: &Message) {
+        self.0.lock().unwrap().push(msg.line.to_string());
+    }
+}
+
+#[test]
+fn tournament_test() {
+    let b = test_bot();
+    let tours = b.clone();
+    let (tx, rx) = mpsc::channel();
+    b.subscribe_tournaments(Box::new(TourListener(tx)));
+
+    run_script(b, &[concat!(
+        ">techcode\n|tournament|create|gen9ou|Single Elimination|0\n",
+        "|tournament|join|Alice\n|tournament|join|Bob\n",
+        "|tournament|start|2\n",
+        "|tournament|battlestart|Alice|Bob|battle-gen9ou-3")], &[]);
+
+    let events: Vec<_> = rx.try_iter().collect();
+    assert_eq!(events.len(), 5);
+    assert_eq!(events[1].0, TournamentEvent::Join("Alice".to_string()));
+    assert_eq!(events[1].1.players, vec!["Alice"]);
+
+    let tour = tours.tournament("techcode").unwrap();
+    assert_eq!(tour.format, "gen9ou");
+    assert!(tour.started);
+    assert_eq!(tour.battles.get("battle-gen9ou-3"),
+               Some(&("Alice".to_string(), "Bob".to_string())));
+    assert!(tours.tournament("lobby").is_none());
+}
+
+#[test]
+fn bad_tournament_event_test() {
+    let b = test_bot();
+    let seen = SeenPlugin::default();
+    let lines = seen.0.clone();
+    b.register(Box::new(seen));
+
+    run_script(b, &[">tourroom\n|tournament|update|{bad"], &[]);
+
+    assert_eq!(*lines.lock().unwrap(), vec!["|tournament|update|{bad"]);
+}